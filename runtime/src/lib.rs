@@ -9,17 +9,22 @@ use runtime_types::*;
 
 impl Context {
     pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_DEPTH, DEFAULT_MAX_DEPTH)
+    }
+    /// creates context with maximum depth of call stack and catch stack
+    ///
+    /// both stacks grow on demand until they reach their limit
+    pub fn with_limits(max_call_depth: usize, max_catch_depth: usize) -> Self {
+        let mut call_stack = Vec::with_capacity(CALL_STACK_SIZE.min(max_call_depth));
+        call_stack.push(CallStack::new());
         Self {
             memory: Memory {
                 stack: Stack {
                     data: vec![],
                     ptr: 0,
-                    call_stack: [CallStack {
-                        end: 0,
-                        code_ptr: 0,
-                        reg_freeze: [Types::Null; FREEZED_REG_SIZE],
-                        pointers_len: 0,
-                    }; CALL_STACK_SIZE],
+                    call_stack,
+                    max_depth: max_call_depth,
+                    globals: 0,
                 },
                 registers: [Types::Null; REGISTER_SIZE],
                 heap: Heap {
@@ -43,20 +48,21 @@ impl Context {
             code: Code {
                 data: vec![],
                 ptr: 0,
+                debug: None,
             },
 
             break_code: None,
             catches: Catches {
-                catches_ptr: 0,
-                cache: [Catch {
-                    code_ptr: 0,
-                    id: None,
-                    cs_ptr: 0,
-                }; CALL_STACK_SIZE],
+                cache: Vec::with_capacity(CALL_STACK_SIZE.min(max_catch_depth)),
+                max_depth: max_catch_depth,
+                pending: vec![],
             },
             exit_code: ExitCodes::End,
 
             libs: vec![],
+            exception: None,
+            watchpoints: vec![],
+            watch_hits: vec![],
         }
     }
     pub fn run(&mut self) {
@@ -73,7 +79,46 @@ impl Context {
             self.memory.gc_sweep_unoptimized()
         }
     }
+    /// executes one instruction, returns false if program should stop
     pub fn read_line(&mut self) -> bool {
+        // any instruction can write to memory, calls pass arguments and errors unwind the stack
+        if self.watchpoints.is_empty() {
+            return self.execute();
+        }
+        let code_ptr = self.code.ptr;
+        let old = self.watch_values();
+        let running = self.execute();
+        self.report_watch_hits(code_ptr, old);
+        running
+    }
+    /// values of watched locations, in order of watchpoints
+    pub fn watch_values(&self) -> Vec<Option<String>> {
+        self.watchpoints
+            .iter()
+            .map(|watchpoint| self.memory.watch_value(watchpoint.loc))
+            .collect()
+    }
+    /// reports watchpoints whose value differs from old after instruction at code_ptr
+    pub fn report_watch_hits(&mut self, code_ptr: usize, old: Vec<Option<String>>) {
+        for (i, old) in old.into_iter().enumerate() {
+            let watchpoint = &self.watchpoints[i];
+            let new = self.memory.watch_value(watchpoint.loc);
+            if old == new {
+                continue;
+            }
+            let hit = WatchHit {
+                watchpoint: i,
+                code_ptr,
+                old,
+                new,
+            };
+            match watchpoint.stop {
+                true => self.watch_hits.push(hit),
+                false => println!("\x1b[90mWatchpoint {} {}: {hit}\x1b[0m", i, watchpoint.loc),
+            }
+        }
+    }
+    fn execute(&mut self) -> bool {
         macro_rules! operation {
             (ptr, $operand: ident, $num1: ident, bool, $r1: expr, $r2: expr, $res: expr) => {
                 if let Types::Pointer(num2, _) = self.memory.registers[$r2] {
@@ -392,11 +437,17 @@ impl Context {
                 self.code.ptr = pos;
             }
             Jump(pos) => {
-                self.memory.stack.call_stack[self.memory.stack.ptr].code_ptr = self.code.ptr;
+                let frame = &mut self.memory.stack.call_stack[self.memory.stack.ptr];
+                frame.code_ptr = self.code.ptr;
+                frame.call = true;
                 self.code.ptr = pos;
             }
             Gotop => {
-                if let Types::Function(u_size) = self.memory.registers[CODE_PTR_REG] {
+                if let Some((u_size, env)) = self.memory.registers[CODE_PTR_REG].as_fun() {
+                    let frame = &mut self.memory.stack.call_stack[self.memory.stack.ptr];
+                    frame.code_ptr = self.code.ptr;
+                    frame.env = env;
+                    frame.call = true;
                     self.code.ptr = self.memory.fun_table[u_size].loc;
                 } else {
                     return self.panic_rt(ErrTypes::InvalidType(
@@ -406,37 +457,30 @@ impl Context {
                 }
             }
             ResD(reg_id) => {
-                if let Types::Function(u_size) = self.memory.registers[reg_id] {
+                if let Some((u_size, _)) = self.memory.registers[reg_id].as_fun() {
                     if let Some((size, pointers_len)) = self.memory.fun_table[u_size].stack_size {
                         let end = self.stack_end() + size;
-                        self.memory.stack.ptr += 1;
-                        if self.memory.stack.ptr >= self.memory.stack.call_stack.len() {
-                            if self.memory.stack.ptr > self.memory.stack.call_stack.len() {
-                                loop {
-                                    println!(
-                                        "Samik mel pravdu, ale tohle stejne nikdy neuvidis ;p"
-                                    );
-                                }
+                        match self.memory.stack.push_frame() {
+                            Ok(frame) => {
+                                frame.end = end;
+                                frame.pointers_len = pointers_len;
                             }
-                            return self.panic_rt(ErrTypes::StackOverflow);
+                            Err(err) => return self.panic_rt(err),
                         }
-                        self.memory.stack.call_stack[self.memory.stack.ptr].end = end;
-                        self.memory.stack.call_stack[self.memory.stack.ptr].pointers_len =
-                            pointers_len;
                         if end > self.memory.stack.data.len() {
                             self.memory.stack.data.resize(end, Types::Null);
                         }
                     }
                 } else {
                     return self.panic_rt(ErrTypes::InvalidType(
-                        self.memory.registers[CODE_PTR_REG],
+                        self.memory.registers[reg_id],
                         Types::Function(0),
                     ));
                 }
                 self.next_line();
             }
             ArgD(id_reg, arg_num, value_reg) => {
-                if let Types::Function(u_size) = self.memory.registers[id_reg] {
+                if let Some((u_size, _)) = self.memory.registers[id_reg].as_fun() {
                     let where_to = &self.memory.fun_table[u_size].params[arg_num];
                     match where_to {
                         MemoryLoc::Stack(offset) => {
@@ -449,8 +493,40 @@ impl Context {
                     }
                 } else {
                     return self.panic_rt(ErrTypes::InvalidType(
-                        self.memory.registers[CODE_PTR_REG],
+                        self.memory.registers[id_reg],
+                        Types::Function(0),
+                    ));
+                }
+                self.next_line();
+            }
+            MkCls(fun_reg, env_reg) => {
+                let fun_id = if let Types::Function(fun_id) = self.memory.registers[fun_reg] {
+                    fun_id
+                } else {
+                    return self.panic_rt(ErrTypes::Expected(
                         Types::Function(0),
+                        self.memory.registers[fun_reg],
+                    ));
+                };
+                if let Types::Pointer(env, PointerTypes::Object) = self.memory.registers[env_reg] {
+                    self.memory.registers[fun_reg] = Types::Closure(fun_id, env);
+                } else {
+                    return self.panic_rt(ErrTypes::Expected(
+                        Types::Pointer(0, PointerTypes::Object),
+                        self.memory.registers[env_reg],
+                    ));
+                }
+                self.next_line();
+            }
+            Cpt(idx, reg) => {
+                // frames that were not entered by a closure have no environment
+                let env = self.memory.stack.call_stack[self.memory.stack.ptr].env;
+                if let Some(value) = env.and_then(|env| self.memory.heap.data.get(env)?.get(idx)) {
+                    self.memory.registers[reg] = *value;
+                } else {
+                    return self.panic_rt(ErrTypes::WrongTypeOperation(
+                        self.memory.registers[reg],
+                        self.code.data[self.code.ptr],
                     ));
                 }
                 self.next_line();
@@ -466,6 +542,13 @@ impl Context {
                 }
             }
             Ret => {
+                if self.memory.stack.ptr == 0 {
+                    return self.panic_rt(ErrTypes::StackUnderflow);
+                }
+                if self.enter_finally() {
+                    return true;
+                }
+                self.drop_catches(self.memory.stack.ptr);
                 self.code.ptr = self.memory.stack.call_stack[self.memory.stack.ptr].code_ptr;
                 self.memory.stack.ptr -= 1;
                 self.next_line();
@@ -483,17 +566,16 @@ impl Context {
             }
             Res(size, pointers_len) => {
                 let end = self.stack_end() + size;
-                self.memory.stack.ptr += 1;
-                if self.memory.stack.ptr >= self.memory.stack.call_stack.len() {
-                    if self.memory.stack.ptr > self.memory.stack.call_stack.len() {
-                        loop {
-                            println!("Samik mel pravdu, ale tohle stejne nikdy neuvidis ;p");
-                        }
+                let code_ptr = self.code.ptr;
+                match self.memory.stack.push_frame() {
+                    Ok(frame) => {
+                        frame.end = end;
+                        frame.pointers_len = pointers_len;
+                        // overwritten by Jump or Gotop if this frame is used for a call
+                        frame.code_ptr = code_ptr;
                     }
-                    return self.panic_rt(ErrTypes::StackOverflow);
+                    Err(err) => return self.panic_rt(err),
                 }
-                self.memory.stack.call_stack[self.memory.stack.ptr].end = end;
-                self.memory.stack.call_stack[self.memory.stack.ptr].pointers_len = pointers_len;
                 if end > self.memory.stack.data.len() {
                     self.memory.stack.data.resize(end, Types::Null);
                 }
@@ -502,7 +584,55 @@ impl Context {
             Frz => {
                 self.memory.stack.call_stack[self.memory.stack.ptr]
                     .reg_freeze
-                    .clone_from_slice(&self.memory.registers[..3]);
+                    .clone_from_slice(&self.memory.registers[..FREEZED_REG_SIZE]);
+                self.next_line();
+            }
+            Call(fun_id) => {
+                if let Err(err) = self.call(fun_id, None) {
+                    return self.panic_rt(err);
+                }
+            }
+            CallDyn(reg) => {
+                if let Some((fun_id, env)) = self.memory.registers[reg].as_fun() {
+                    if let Err(err) = self.call(fun_id, env) {
+                        return self.panic_rt(err);
+                    }
+                } else {
+                    return self.panic_rt(ErrTypes::InvalidType(
+                        self.memory.registers[reg],
+                        Types::Function(0),
+                    ));
+                }
+            }
+            TailCall(fun_id) => {
+                if let Err(err) = self.tail_call(fun_id, None) {
+                    return self.panic_rt(err);
+                }
+            }
+            TailCallDyn(reg) => {
+                if let Some((fun_id, env)) = self.memory.registers[reg].as_fun() {
+                    if let Err(err) = self.tail_call(fun_id, env) {
+                        return self.panic_rt(err);
+                    }
+                } else {
+                    return self.panic_rt(ErrTypes::InvalidType(
+                        self.memory.registers[reg],
+                        Types::Function(0),
+                    ));
+                }
+            }
+            Return => {
+                if self.memory.stack.ptr == 0 {
+                    return self.panic_rt(ErrTypes::StackUnderflow);
+                }
+                if self.enter_finally() {
+                    return true;
+                }
+                self.drop_catches(self.memory.stack.ptr);
+                let frame = self.memory.stack.call_stack[self.memory.stack.ptr];
+                self.memory.registers[..FREEZED_REG_SIZE].clone_from_slice(&frame.reg_freeze);
+                self.code.ptr = frame.code_ptr;
+                self.memory.stack.ptr -= 1;
                 self.next_line();
             }
             Swap(reg1, reg2) => {
@@ -851,9 +981,22 @@ impl Context {
                         self.memory.registers[np_reg],
                     ));
                 }
+                self.next_line();
+            }
+            NPSub(np_reg, id) => {
+                if let Types::NonPrimitive(id_dyn) = self.memory.registers[np_reg] {
+                    self.memory.registers[GENERAL_REG3] =
+                        Types::Bool(self.memory.is_subtype(id_dyn, id));
+                } else {
+                    return self.panic_rt(ErrTypes::Expected(
+                        Types::NonPrimitive(0),
+                        self.memory.registers[np_reg],
+                    ));
+                }
+                self.next_line();
             }
             Cast(reg1, ttype) => {
-                match Self::cast(&mut self.memory.registers, reg1, ttype) {
+                match Self::cast(&mut self.memory, reg1, ttype) {
                     Ok(value) => {
                         self.memory.registers[reg1] = value;
                     }
@@ -870,9 +1013,12 @@ impl Context {
                     code_ptr: self.code.ptr,
                     id: None,
                     cs_ptr: self.memory.stack.ptr,
+                    reg_freeze: self.freeze_registers(),
+                    finally: false,
                 }) {
                     return self.panic_rt(err);
                 }
+                self.memory.registers[CATCH_REG] = Types::Bool(false);
                 self.next_line()
             }
             CatchId(id) => {
@@ -880,15 +1026,52 @@ impl Context {
                     code_ptr: self.code.ptr,
                     id: Some(id),
                     cs_ptr: self.memory.stack.ptr,
+                    reg_freeze: self.freeze_registers(),
+                    finally: false,
                 }) {
                     return self.panic_rt(err);
                 }
+                self.memory.registers[CATCH_REG] = Types::Bool(false);
                 self.next_line()
             }
-            DelCatch => {
-                self.catches.pop();
+            DelCatch => match self.catches.cache.pop() {
+                Some(catch) if catch.finally => {
+                    self.catches.start_finally(
+                        catch.cs_ptr,
+                        FinallyExit::Resume(self.code.ptr + 1, self.memory.registers),
+                    );
+                    self.memory.registers[CATCH_REG] = Types::Bool(true);
+                    self.code.ptr = catch.code_ptr + 1;
+                }
+                _ => self.next_line(),
+            },
+            Finally => {
+                if let Err(err) = self.catches.push(runtime_types::Catch {
+                    code_ptr: self.code.ptr,
+                    id: None,
+                    cs_ptr: self.memory.stack.ptr,
+                    reg_freeze: self.freeze_registers(),
+                    finally: true,
+                }) {
+                    return self.panic_rt(err);
+                }
+                self.memory.registers[CATCH_REG] = Types::Bool(false);
                 self.next_line()
             }
+            EndFinally => match self.catches.pending.pop().map(|pending| pending.exit) {
+                Some(FinallyExit::Resume(code_ptr, registers)) => {
+                    self.memory.registers = registers;
+                    self.code.ptr = code_ptr;
+                }
+                Some(FinallyExit::Propagate(exception)) => {
+                    self.memory.registers[EXCEPTION_REG] = exception.value;
+                    self.exception = Some(exception);
+                    if !self.enter_panic() {
+                        return self.uncaught();
+                    }
+                }
+                None => self.next_line(),
+            },
             /*StrCpy(reg) => {
                 if let Types::Pointer(u_size, PointerTypes::String) = self.memory.registers[reg] {
                     self.memory.registers[POINTER_REG] =
@@ -916,19 +1099,160 @@ impl Context {
                 self.next_line();
             }
             Panic => {
-                self.enter_panic();
+                let value = self.memory.registers[EXCEPTION_REG];
+                if !self.throw(value, None) {
+                    return self.uncaught();
+                }
+            }
+            Throw(reg) => {
+                let value = self.memory.registers[reg];
+                if !self.throw(value, None) {
+                    return self.uncaught();
+                }
+            }
+            Trace => {
+                let trace = match &self.exception {
+                    Some(exception) => self.format_trace(&exception.trace),
+                    None => String::new(),
+                };
+                self.memory.registers[POINTER_REG] =
+                    Types::Pointer(self.memory.strings.from_string(trace), PointerTypes::String);
                 self.next_line();
             }
         }
         return true;
     }
-    fn stack_end(&self) -> usize {
+    pub fn stack_end(&self) -> usize {
         self.memory.stack.call_stack[self.memory.stack.ptr].end
     }
     fn next_line(&mut self) {
         self.code.ptr += 1;
     }
-    fn cast(registers: &mut Registers, reg1: usize, reg2: usize) -> Result<Types, ErrTypes> {
+    /// calls function from fun_table
+    ///
+    /// arguments are taken from reg(0), reg(1), ... and moved to their destinations
+    /// given by params, registers are freezed on the new frame and current code ptr is saved
+    ///
+    /// env is the captured environment of a closure
+    fn call(&mut self, fun_id: usize, env: Option<usize>) -> Result<(), ErrTypes> {
+        let fun = self
+            .memory
+            .fun_table
+            .get(fun_id)
+            .ok_or(ErrTypes::InvalidFunction(fun_id))?;
+        let (size, pointers_len) = fun.stack_size.unwrap_or((0, 0));
+        let loc = fun.loc;
+        let end = self.stack_end() + size;
+        let frame = self.memory.stack.push_frame()?;
+        frame.end = end;
+        frame.pointers_len = pointers_len;
+        frame.code_ptr = self.code.ptr;
+        frame.env = env;
+        frame.call = true;
+        frame
+            .reg_freeze
+            .clone_from_slice(&self.memory.registers[..FREEZED_REG_SIZE]);
+        if end > self.memory.stack.data.len() {
+            self.memory.stack.data.resize(end, Types::Null);
+        }
+        self.pass_args(fun_id);
+        self.code.ptr = loc;
+        Ok(())
+    }
+    /// calls function from fun_table reusing the current frame
+    ///
+    /// frame end is recalculated from stack_size of the new function, code ptr
+    /// and freezed registers of the frame are kept so Return goes back to the original caller,
+    /// catch blocks of the replaced function are dropped
+    ///
+    /// frames of blocks reserved by Res inside of the function are unwound first, only frames
+    /// entered by a call are reused, tail call outside of any function is a plain call
+    fn tail_call(&mut self, fun_id: usize, env: Option<usize>) -> Result<(), ErrTypes> {
+        let stack = &self.memory.stack;
+        let Some(ptr) = (1..=stack.ptr).rev().find(|idx| stack.call_stack[*idx].call) else {
+            return self.call(fun_id, env);
+        };
+        let fun = self
+            .memory
+            .fun_table
+            .get(fun_id)
+            .ok_or(ErrTypes::InvalidFunction(fun_id))?;
+        let (size, pointers_len) = fun.stack_size.unwrap_or((0, 0));
+        let loc = fun.loc;
+        self.drop_catches(ptr);
+        self.unwind(ptr);
+        let end = self.memory.stack.call_stack[ptr - 1].end + size;
+        let frame = &mut self.memory.stack.call_stack[ptr];
+        frame.end = end;
+        frame.pointers_len = pointers_len;
+        frame.env = env;
+        frame.call = true;
+        if end > self.memory.stack.data.len() {
+            self.memory.stack.data.resize(end, Types::Null);
+        }
+        self.pass_args(fun_id);
+        self.code.ptr = loc;
+        Ok(())
+    }
+    /// drops catch blocks registered in frame cs_ptr or above it together with finally blocks
+    /// running in these frames, used when the frame is left without an exception
+    fn drop_catches(&mut self, cs_ptr: usize) {
+        while self
+            .catches
+            .cache
+            .last()
+            .is_some_and(|catch| catch.cs_ptr >= cs_ptr)
+        {
+            self.catches.cache.pop();
+        }
+        self.catches.pending.retain(|pending| pending.cs_ptr < cs_ptr);
+    }
+    /// runs the innermost finally block registered in the current frame before the frame is left
+    /// by Ret or Return, catch blocks inside of it are dropped
+    ///
+    /// the return instruction runs again when the block reaches EndFinally
+    fn enter_finally(&mut self) -> bool {
+        let ptr = self.memory.stack.ptr;
+        while let Some(catch) = self.catches.cache.pop_if(|catch| catch.cs_ptr >= ptr) {
+            if catch.finally && catch.cs_ptr == ptr {
+                self.catches.start_finally(
+                    ptr,
+                    FinallyExit::Resume(self.code.ptr, self.memory.registers),
+                );
+                self.memory.registers[CATCH_REG] = Types::Bool(true);
+                self.code.ptr = catch.code_ptr + 1;
+                return true;
+            }
+        }
+        false
+    }
+    /// moves arguments from reg(0), reg(1), ... to params of function
+    /// expects frame of the function to be already on the stack
+    fn pass_args(&mut self, fun_id: usize) {
+        let params = &self.memory.fun_table[fun_id].params;
+        let args = self.memory.registers;
+        let end = self.stack_end();
+        for (param, arg) in params.iter().zip(args.iter()) {
+            match param {
+                MemoryLoc::Stack(offset) => {
+                    self.memory.stack.data[end - offset] = *arg;
+                }
+                MemoryLoc::Register(reg) => {
+                    self.memory.registers[*reg] = *arg;
+                }
+            }
+        }
+    }
+    fn cast(memory: &mut Memory, reg1: usize, reg2: usize) -> Result<Types, ErrTypes> {
+        // anything can be cast into a string
+        if let Types::Pointer(_, PointerTypes::String) = memory.registers[reg2] {
+            let str = memory.registers[reg1].to_str(memory);
+            return Ok(Types::Pointer(
+                memory.strings.from_string(str),
+                PointerTypes::String,
+            ));
+        }
+        let registers = &memory.registers;
         match registers[reg1] {
             Types::Bool(bol) => match registers[reg2] {
                 Types::Int(_) => {
@@ -971,6 +1295,12 @@ impl Context {
                         Ok(Types::Bool(true))
                     }
                 }
+                Types::Char(_) => {
+                    return match u32::try_from(num).ok().and_then(char::from_u32) {
+                        Some(chr) => Ok(Types::Char(chr)),
+                        None => Err(ErrTypes::InvalidChar(registers[reg1])),
+                    }
+                }
                 _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
             },
             Types::Float(num) => match registers[reg2] {
@@ -983,6 +1313,18 @@ impl Context {
                         Ok(Types::Bool(true))
                     }
                 }
+                Types::Char(_) => {
+                    // only whole numbers are character codes, NaN and infinity are not
+                    let chr = if num.fract() == 0f64 && num >= 0f64 && num <= u32::MAX as f64 {
+                        char::from_u32(num as u32)
+                    } else {
+                        None
+                    };
+                    return match chr {
+                        Some(chr) => Ok(Types::Char(chr)),
+                        None => Err(ErrTypes::InvalidChar(registers[reg1])),
+                    };
+                }
                 _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
             },
             Types::Usize(num) => match registers[reg2] {
@@ -995,54 +1337,192 @@ impl Context {
                         Ok(Types::Bool(true))
                     }
                 }
+                Types::Char(_) => {
+                    return match u32::try_from(num).ok().and_then(char::from_u32) {
+                        Some(chr) => Ok(Types::Char(chr)),
+                        None => Err(ErrTypes::InvalidChar(registers[reg1])),
+                    }
+                }
                 _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
             },
+            Types::Char(chr) => match registers[reg2] {
+                Types::Int(_) => return Ok(Types::Int(chr as i64)),
+                Types::Usize(_) => return Ok(Types::Usize(chr as usize)),
+                Types::Float(_) => return Ok(Types::Float(chr as u32 as f64)),
+                _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
+            },
+            Types::Pointer(loc, PointerTypes::String) => {
+                let text = memory.strings.to_string(loc);
+                let parsed = match registers[reg2] {
+                    Types::Int(_) => text.trim().parse().ok().map(Types::Int),
+                    Types::Float(_) => text.trim().parse().ok().map(Types::Float),
+                    Types::Usize(_) => text.trim().parse().ok().map(Types::Usize),
+                    Types::Bool(_) => text.trim().parse().ok().map(Types::Bool),
+                    _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
+                };
+                return match parsed {
+                    Some(value) => Ok(value),
+                    None => Err(ErrTypes::ParseError(text, registers[reg2])),
+                };
+            }
             _ => return Err(ErrTypes::ImplicitCast(registers[reg1], registers[reg2])),
         }
     }
+    /// Throws runtime error as an exception object [NonPrimitive(kind.id()), Usize(kind.code()), message],
+    /// reports it and exits if it was not caught.
     fn panic_rt(&mut self, kind: ErrTypes) -> bool {
-        if self.enter_panic() {
+        let message = self.memory.strings.from_string(kind.message());
+        let obj = self.memory.allocate_obj(3);
+        self.memory.heap.data[obj] = vec![
+            Types::NonPrimitive(kind.id()),
+            Types::Usize(kind.code() as usize),
+            Types::Pointer(message, PointerTypes::String),
+        ];
+        if self.throw(Types::Pointer(obj, PointerTypes::Object), Some(kind)) {
             return true;
         }
-        self.break_code = Some(self.code.ptr);
-        println!("{}", get_message(&kind, Some((self.code.ptr, 0))));
-        self.exit_code = ExitCodes::Internal(kind);
+        self.uncaught()
+    }
+    /// Throws value as an exception and captures stack trace. Value is handed to the catch block
+    /// in reg(EXCEPTION_REG). Returns false if the exception was not caught.
+    fn throw(&mut self, value: Types, error: Option<ErrTypes>) -> bool {
+        self.exception = Some(Exception {
+            value,
+            trace: self.stack_trace(),
+            error,
+        });
+        self.memory.registers[EXCEPTION_REG] = value;
+        self.enter_panic()
+    }
+    /// reports exception that was not caught, always returns false
+    fn uncaught(&mut self) -> bool {
+        let exception = match self.exception.clone() {
+            Some(exception) => exception,
+            None => return false,
+        };
+        self.break_code = Some(exception.trace[0]);
+        match exception.error {
+            Some(kind) => {
+                println!(
+                    "{}",
+                    get_message_trace(&kind, &self.format_trace(&exception.trace))
+                );
+                self.exit_code = ExitCodes::Internal(kind);
+            }
+            None => {
+                println!(
+                    "\x1b[91mUncaught exception {:+}\x1b[0m\n\x1b[90m{}\x1b[0m",
+                    exception.value,
+                    self.format_trace(&exception.trace)
+                );
+            }
+        }
         false
     }
-    /// This function is called when an exception is thrown. It will search for a catch block
-    /// that matches the exception type. If it finds one, it will set the code pointer to the
-    /// catch block and set the stack pointer to the catch block's stack pointer. If it doesn't
-    /// find one, it will set the exit code to exception and return false. (indicating that the VM should exit)
+    /// This function is called when an exception is thrown. It walks the catch stack outwards,
+    /// popping every catch block that does not match the exception type. When it finds one that
+    /// matches, it unwinds the call stack to the frame of the catch block, restores registers
+    /// freezed by the catch block, sets reg(CATCH_REG) to true, reg(EXCEPTION_REG) to the exception
+    /// and continues after the catch block. Finally blocks always match, the exception continues
+    /// propagating when they reach EndFinally. If it doesn't find any, it will set the exit code
+    /// to exception and return false. (indicating that the VM should exit)
     fn enter_panic(&mut self) -> bool {
-        let mut i = self.catches.catches_ptr;
-        loop {
-            if i == 0 {
-                self.exit_code = ExitCodes::Exception;
-                return false;
-            }
-            i -= 1;
-            if let Some(n) = self.catches.cache[i].id {
-                if let Types::NonPrimitive(e_type) = self.memory.registers[RETURN_REG] {
-                    if n == e_type {
-                        self.code.ptr = self.catches.cache[i].code_ptr;
-                        self.memory.stack.ptr = self.catches.cache[i].cs_ptr;
-                    }
+        let exception = self.memory.registers[EXCEPTION_REG];
+        let e_type = self.memory.type_id(exception);
+        while let Some(catch) = self.catches.cache.pop() {
+            // frame of this catch block has already returned
+            if catch.cs_ptr > self.memory.stack.ptr {
+                continue;
+            }
+            if let Some(id) = catch.id {
+                match e_type {
+                    Some(e_type) if self.memory.is_subtype(e_type, id) => {}
+                    _ => continue,
                 }
-                break;
-            } else {
-                self.code.ptr = self.catches.cache[i].code_ptr;
-                self.memory.stack.ptr = self.catches.cache[i].cs_ptr;
-                break;
+            }
+            self.catches.abandon_finally();
+            if catch.finally {
+                // kept aside, the finally block may throw and catch exceptions of its own
+                let pending = self.exception.clone().unwrap_or(Exception {
+                    value: exception,
+                    trace: self.stack_trace(),
+                    error: None,
+                });
+                self.catches
+                    .start_finally(catch.cs_ptr, FinallyExit::Propagate(pending));
+            }
+            self.unwind(catch.cs_ptr);
+            self.memory.registers[..FREEZED_REG_SIZE].clone_from_slice(&catch.reg_freeze);
+            self.memory.registers[CATCH_REG] = Types::Bool(true);
+            self.memory.registers[POINTER_REG] = Types::Null;
+            self.memory.registers[EXCEPTION_REG] = exception;
+            self.memory.registers[CODE_PTR_REG] = Types::Null;
+            self.code.ptr = catch.code_ptr + 1;
+            return true;
+        }
+        self.exit_code = ExitCodes::Exception;
+        false
+    }
+    /// pops all frames above cs_ptr and clears their part of the stack so they no longer act as GC roots
+    fn unwind(&mut self, cs_ptr: usize) {
+        let stack = &mut self.memory.stack;
+        let end = stack.call_stack[cs_ptr].end;
+        let abandoned_end = stack.call_stack[cs_ptr..=stack.ptr]
+            .iter()
+            .map(|frame| frame.end)
+            .max()
+            .unwrap_or(end)
+            .min(stack.data.len());
+        if end < abandoned_end {
+            stack.data[end..abandoned_end].fill(Types::Null);
+        }
+        stack.ptr = cs_ptr;
+    }
+    fn freeze_registers(&self) -> [Types; FREEZED_REG_SIZE] {
+        let mut reg_freeze = [Types::Null; FREEZED_REG_SIZE];
+        reg_freeze.clone_from_slice(&self.memory.registers[..FREEZED_REG_SIZE]);
+        reg_freeze
+    }
+    /// captures code pointers of all functions on the call stack, innermost first
+    pub fn stack_trace(&self) -> Vec<usize> {
+        let mut trace = vec![self.code.ptr];
+        for idx in self.call_frames() {
+            trace.push(self.memory.stack.call_stack[idx].code_ptr);
+        }
+        trace
+    }
+    /// indexes of frames entered by a call, innermost first
+    ///
+    /// frames reserved by Res for blocks belong to the function that reserved them
+    pub fn call_frames(&self) -> Vec<usize> {
+        (1..=self.memory.stack.ptr)
+            .rev()
+            .filter(|idx| self.memory.stack.call_stack[*idx].call)
+            .collect()
+    }
+    /// formats stack trace using names from fun_table and source locations from debug info, one frame per line
+    pub fn format_trace(&self, trace: &[usize]) -> String {
+        let mut res = Vec::with_capacity(trace.len());
+        for ptr in trace {
+            let name = match self.memory.fun_at(*ptr) {
+                Some(fun) => self.memory.fun_table[fun].name.as_str(),
+                None => "<main>",
+            };
+            match self.location(*ptr) {
+                Some(location) => res.push(format!("at {name} ({location})")),
+                None => res.push(format!("at {name} ({ptr})")),
             }
         }
-        self.catches.truncate(i);
-        true
+        res.join("\n")
+    }
+    /// returns file:line:column of instruction if debug info is present
+    pub fn location(&self, code_ptr: usize) -> Option<String> {
+        self.code.debug.as_ref()?.format_location(code_ptr)
     }
     pub fn size(&self) -> usize {
         self.memory.size()
             + std::mem::size_of_val(&self.break_code)
-            + std::mem::size_of_val(&self.catches.cache)
-            + std::mem::size_of_val(&self.catches.catches_ptr)
+            + self.catches.cache.capacity() * std::mem::size_of::<Catch>()
             + std::mem::size_of_val(&self.code)
             + std::mem::size_of_val(&self.exit_code)
             + std::mem::size_of_val(&self.libs)
@@ -1053,19 +1533,27 @@ impl Context {
 }
 #[allow(unused)]
 pub mod runtime_types {
+    /// initial capacity of call stack and catch stack
     pub const CALL_STACK_SIZE: usize = 256;
+    /// default limit for depth of call stack and catch stack
+    pub const DEFAULT_MAX_DEPTH: usize = 65536;
     pub const FREEZED_REG_SIZE: usize = 6;
     pub type Registers = [Types; REGISTER_SIZE];
-    pub const REGISTER_SIZE: usize = 9;
+    pub const REGISTER_SIZE: usize = 10;
     pub const GENERAL_REG1: usize = 0;
     pub const GENERAL_REG2: usize = 1;
     pub const GENERAL_REG3: usize = 2;
     pub const GENERAL_REG4: usize = 3;
     pub const GENERAL_REG5: usize = 4;
     pub const GENERAL_REG6: usize = 5;
-    pub const POINTER_REG: usize = REGISTER_SIZE - 3;
-    pub const RETURN_REG: usize = REGISTER_SIZE - 2;
-    pub const CODE_PTR_REG: usize = REGISTER_SIZE - 1;
+    pub const POINTER_REG: usize = REGISTER_SIZE - 4;
+    pub const RETURN_REG: usize = REGISTER_SIZE - 3;
+    pub const CODE_PTR_REG: usize = REGISTER_SIZE - 2;
+    /// set to false when catch or finally block is registered and to true when its handler runs,
+    /// general registers are left to the program
+    pub const CATCH_REG: usize = REGISTER_SIZE - 1;
+    /// register in which catch block receives the exception
+    pub const EXCEPTION_REG: usize = RETURN_REG;
     /// context for a single thread of execution (may include multiple threads in future updates)
     /// this is the main struct that holds all the data for the runtime
     pub struct Context {
@@ -1075,6 +1563,63 @@ pub mod runtime_types {
         pub catches: Catches,
         pub exit_code: ExitCodes,
         pub libs: Libs,
+        /// last thrown exception
+        pub exception: Option<Exception>,
+        /// locations checked after every instruction that may write to memory
+        pub watchpoints: Vec<Watchpoint>,
+        /// changes of stopping watchpoints, consumed by debugger
+        pub watch_hits: Vec<WatchHit>,
+    }
+    /// memory location observed by watchpoint
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum WatchLoc {
+        /// object, index in object
+        Heap(usize, usize),
+        /// absolute index on stack
+        Stack(usize),
+        /// whole string
+        String(usize),
+    }
+    #[derive(Debug, Clone)]
+    pub struct Watchpoint {
+        pub loc: WatchLoc,
+        /// stop execution on change, otherwise only log it
+        pub stop: bool,
+    }
+    /// change of watched location
+    #[derive(Debug, Clone)]
+    pub struct WatchHit {
+        /// index in Context.watchpoints
+        pub watchpoint: usize,
+        /// instruction that made the change
+        pub code_ptr: usize,
+        pub old: Option<String>,
+        pub new: Option<String>,
+    }
+    impl fmt::Display for WatchLoc {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                WatchLoc::Heap(obj, idx) => write!(f, "heap[{obj}][{idx}]"),
+                WatchLoc::Stack(idx) => write!(f, "stack[{idx}]"),
+                WatchLoc::String(idx) => write!(f, "string[{idx}]"),
+            }
+        }
+    }
+    impl fmt::Display for WatchHit {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let old = self.old.as_deref().unwrap_or("<none>");
+            let new = self.new.as_deref().unwrap_or("<none>");
+            write!(f, "{old} -> {new} at {}", self.code_ptr)
+        }
+    }
+    /// exception together with the place where it was thrown
+    #[derive(Debug, Clone)]
+    pub struct Exception {
+        pub value: Types,
+        /// code pointers of functions on the call stack, innermost first
+        pub trace: Vec<usize>,
+        /// runtime error this exception was made from
+        pub error: Option<ErrTypes>,
     }
     pub struct Memory {
         pub stack: Stack,
@@ -1137,6 +1682,23 @@ pub mod runtime_types {
                 }
             }
         }
+        /// current value of watched location as text, None if it does not exist
+        pub fn watch_value(&self, loc: WatchLoc) -> Option<String> {
+            match loc {
+                WatchLoc::Heap(obj, idx) => self
+                    .heap
+                    .data
+                    .get(obj)?
+                    .get(idx)
+                    .map(|value| format!("{value:+}")),
+                WatchLoc::Stack(idx) => self.stack.data.get(idx).map(|value| format!("{value:+}")),
+                WatchLoc::String(idx) => self
+                    .strings
+                    .pool
+                    .get(idx)
+                    .map(|str| format!("{:?}", str.iter().collect::<String>())),
+            }
+        }
         pub fn last_obj(&mut self) -> usize {
             if self.heap.data.is_empty() {
                 return 0;
@@ -1173,6 +1735,40 @@ pub mod runtime_types {
         pub fn resize_obj(&mut self, heap_idx: usize, new_size: usize) {
             self.heap.data[heap_idx].resize(new_size, Types::Null)
         }
+        /// returns ID of non-primitive type of value, which is either the header itself
+        /// or an object with the header at index 0
+        pub fn type_id(&self, value: Types) -> Option<usize> {
+            match value {
+                Types::NonPrimitive(id) => Some(id),
+                Types::Pointer(u_size, PointerTypes::Object) => match self.heap.data[u_size].first() {
+                    Some(Types::NonPrimitive(id)) => Some(*id),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        /// returns true if non-primitive type id is ancestor or the same type as id
+        pub fn is_subtype(&self, id: usize, ancestor: usize) -> bool {
+            let mut current = Some(id);
+            // bounded by number of types in case of a cycle in parents
+            for _ in 0..=self.non_primitives.len() {
+                match current {
+                    Some(id) if id == ancestor => return true,
+                    Some(id) => current = self.non_primitives.get(id).and_then(|np| np.parent),
+                    None => return false,
+                }
+            }
+            false
+        }
+        /// returns index of function in fun_table that contains instruction at code_ptr
+        pub fn fun_at(&self, code_ptr: usize) -> Option<usize> {
+            self.fun_table
+                .iter()
+                .enumerate()
+                .filter(|(_, fun)| fun.loc <= code_ptr)
+                .max_by_key(|(_, fun)| fun.loc)
+                .map(|(idx, _)| idx)
+        }
         /// GC
         pub fn gc_sweep(&mut self) {
             if self.gc.disabled {
@@ -1189,6 +1785,7 @@ pub mod runtime_types {
             self.gc_sweep_marked(marked);
         }
         pub fn gc_sweep_marked(&mut self, marked: (Vec<bool>, Vec<bool>)) {
+            self.gc.sweeps_count += 1;
             self.gc_sweep_marked_obj(marked.0);
             self.gc_sweep_marked_string(marked.1);
             let last = self.last_string();
@@ -1249,7 +1846,13 @@ pub mod runtime_types {
             marked_obj.resize(self.heap.data.len(), true);
             marked_str.resize(self.strings.pool.len(), true);
             self.gc_mark_registers(&mut marked_obj, &mut marked_str);
-            self.gc_mark_range((0, self.stack.data.len()), &mut marked_obj, &mut marked_str);
+            self.gc_mark_envs(&mut marked_obj, &mut marked_str);
+            // frames above the current one have returned, their values are not roots
+            let live = self.stack.call_stack[self.stack.ptr]
+                .end
+                .max(self.stack.globals)
+                .min(self.stack.data.len());
+            self.gc_mark_range((0, live), &mut marked_obj, &mut marked_str);
             (marked_obj, marked_str)
         }
         pub fn gc_mark(&mut self) -> (Vec<bool>, Vec<bool>) {
@@ -1259,6 +1862,7 @@ pub mod runtime_types {
             marked.resize(self.heap.data.len(), true);
             marked_str.resize(self.strings.pool.len(), true);
             self.gc_mark_registers(&mut marked, &mut marked_str);
+            self.gc_mark_envs(&mut marked, &mut marked_str);
             while call_stack_idx <= self.stack.ptr {
                 let cs = self.stack.call_stack[call_stack_idx];
                 let prev_cs = self.stack.call_stack[call_stack_idx - 1];
@@ -1271,6 +1875,8 @@ pub mod runtime_types {
             }
             (marked, marked_str)
         }
+        /// marks object and everything reachable from it, objects waiting to be scanned
+        /// are kept in a worklist so depth of the object graph is not limited by the host stack
         pub fn gc_mark_obj(
             &mut self,
             obj_idx: usize,
@@ -1283,15 +1889,35 @@ pub mod runtime_types {
             if !marked[obj_idx] {
                 return;
             }
-            marked[obj_idx] = false;
-            for idx in 0..self.heap.data[obj_idx].len() {
-                let member = self.heap.data[obj_idx][idx];
-                if let Types::Pointer(u_size, PointerTypes::Object) = member {
-                    self.gc_mark_obj(u_size, marked, marked_str);
-                } else if let Types::Pointer(u_size, PointerTypes::Heap(_)) = member {
-                    self.gc_mark_obj(u_size, marked, marked_str);
-                } else if let Types::Pointer(u_size, PointerTypes::String) = member {
-                    self.gc_mark_string(u_size, marked_str)
+            let mut worklist = vec![obj_idx];
+            while let Some(obj_idx) = worklist.pop() {
+                // object may have been pushed more than once before it was scanned
+                if !marked[obj_idx] {
+                    continue;
+                }
+                marked[obj_idx] = false;
+                let obj = &self.heap.data[obj_idx];
+                // objects with a header are scanned using layout of their type
+                let fields = match obj.first() {
+                    Some(Types::NonPrimitive(id)) => self
+                        .non_primitives
+                        .get(*id)
+                        .and_then(NonPrimitiveType::scanned_fields),
+                    _ => None,
+                };
+                let mut mark = |member: &Types| match *member {
+                    Types::Pointer(u_size, PointerTypes::Object | PointerTypes::Heap(_))
+                    | Types::Closure(_, u_size) => {
+                        if marked[u_size] {
+                            worklist.push(u_size);
+                        }
+                    }
+                    Types::Pointer(u_size, PointerTypes::String) => marked_str[u_size] = false,
+                    _ => {}
+                };
+                match fields {
+                    Some(fields) => fields.iter().filter_map(|idx| obj.get(*idx)).for_each(mark),
+                    None => obj.iter().for_each(mark),
                 }
             }
         }
@@ -1311,6 +1937,8 @@ pub mod runtime_types {
                     self.gc_mark_obj(u_size, marked_obj, marked_string);
                 } else if let Types::Pointer(u_size, PointerTypes::String) = self.stack.data[idx] {
                     self.gc_mark_string(u_size, marked_string);
+                } else if let Types::Closure(_, env) = self.stack.data[idx] {
+                    self.gc_mark_obj(env, marked_obj, marked_string);
                 }
             }
         }
@@ -1322,6 +1950,16 @@ pub mod runtime_types {
                     self.gc_mark_obj(u_size, marked, marked_str);
                 } else if let Types::Pointer(u_size, PointerTypes::String) = reg {
                     self.gc_mark_string(u_size, marked_str);
+                } else if let Types::Closure(_, env) = reg {
+                    self.gc_mark_obj(env, marked, marked_str);
+                }
+            }
+        }
+        /// marks environments of closures that are currently running
+        pub fn gc_mark_envs(&mut self, marked: &mut Vec<bool>, marked_str: &mut Vec<bool>) {
+            for idx in 0..=self.stack.ptr {
+                if let Some(env) = self.stack.call_stack[idx].env {
+                    self.gc_mark_obj(env, marked, marked_str);
                 }
             }
         }
@@ -1331,7 +1969,7 @@ pub mod runtime_types {
                 + std::mem::size_of_val(&self.heap.data)
                 + std::mem::size_of_val(&self.stack)
                 + std::mem::size_of_val(&self.stack.data)
-                + std::mem::size_of_val(&self.stack.call_stack)
+                + self.stack.call_stack.capacity() * std::mem::size_of::<CallStack>()
                 + std::mem::size_of_val(&self.stack.ptr)
                 + std::mem::size_of_val(&self.strings.pool)
                 + std::mem::size_of_val(&self.registers)
@@ -1342,7 +1980,29 @@ pub mod runtime_types {
     pub struct Stack {
         pub data: Vec<Types>,
         pub ptr: usize,
-        pub call_stack: [CallStack; CALL_STACK_SIZE],
+        pub call_stack: Vec<CallStack>,
+        /// call stack overflows when ptr reaches this depth
+        pub max_depth: usize,
+        /// number of values at the start of the stack that live for the whole program,
+        /// constants and globals of the loaded program
+        pub globals: usize,
+    }
+    impl Stack {
+        /// advances call stack and returns the new frame, call stack grows if needed
+        ///
+        /// reused frames are reset, nothing is left from the frame that was there before
+        pub fn push_frame(&mut self) -> Result<&mut CallStack, ErrTypes> {
+            if self.ptr + 1 >= self.max_depth {
+                return Err(ErrTypes::StackOverflow);
+            }
+            self.ptr += 1;
+            if self.ptr == self.call_stack.len() {
+                self.call_stack.push(CallStack::new());
+            } else {
+                self.call_stack[self.ptr] = CallStack::new();
+            }
+            Ok(&mut self.call_stack[self.ptr])
+        }
     }
     pub struct Heap {
         pub data: Vec<Vec<Types>>,
@@ -1430,6 +2090,73 @@ pub mod runtime_types {
     pub struct Code {
         pub data: Vec<Instructions>,
         pub ptr: usize,
+        /// source mapping emitted by compiler, used in error messages and debuggers
+        pub debug: Option<DebugInfo>,
+    }
+    /// optional debug section of binary
+    #[derive(Debug, Clone, Default)]
+    pub struct DebugInfo {
+        /// source files referenced by line table
+        pub files: Vec<String>,
+        /// sorted by instruction index, each entry applies until the next one
+        pub lines: Vec<LineEntry>,
+        /// names of local variables
+        pub locals: Vec<LocalVar>,
+        /// sorted by instruction index, each annotation applies until the next one for the same register
+        pub registers: Vec<RegAnnotation>,
+    }
+    #[derive(Debug, Clone)]
+    pub struct LineEntry {
+        /// index of first instruction
+        pub ptr: usize,
+        /// index into DebugInfo.files
+        pub file: usize,
+        pub line: usize,
+        pub column: usize,
+    }
+    #[derive(Debug, Clone)]
+    pub struct LocalVar {
+        /// index of function in fun_table
+        pub fun: usize,
+        /// offset from end of stack frame, same as MemoryLoc::Stack
+        pub offset: usize,
+        pub name: String,
+    }
+    #[derive(Debug, Clone)]
+    pub struct RegAnnotation {
+        /// index of first instruction
+        pub ptr: usize,
+        pub reg: usize,
+        pub name: String,
+    }
+    impl DebugInfo {
+        /// returns source location of instruction at code_ptr
+        pub fn location(&self, code_ptr: usize) -> Option<&LineEntry> {
+            let idx = self.lines.partition_point(|entry| entry.ptr <= code_ptr);
+            self.lines.get(idx.checked_sub(1)?)
+        }
+        /// returns file:line:column of instruction at code_ptr
+        pub fn format_location(&self, code_ptr: usize) -> Option<String> {
+            let entry = self.location(code_ptr)?;
+            let file = self.files.get(entry.file).map_or("?", |f| f.as_str());
+            Some(format!("{file}:{}:{}", entry.line, entry.column))
+        }
+        /// returns name of local variable at stack offset of function
+        pub fn local_name(&self, fun: usize, offset: usize) -> Option<&str> {
+            self.locals
+                .iter()
+                .find(|local| local.fun == fun && local.offset == offset)
+                .map(|local| local.name.as_str())
+        }
+        /// returns annotation of register at instruction code_ptr
+        pub fn register_name(&self, code_ptr: usize, reg: usize) -> Option<&str> {
+            self.registers
+                .iter()
+                .take_while(|annotation| annotation.ptr <= code_ptr)
+                .filter(|annotation| annotation.reg == reg)
+                .last()
+                .map(|annotation| annotation.name.as_str())
+        }
     }
     #[derive(Debug)]
     pub struct FunSpec {
@@ -1455,28 +2182,61 @@ pub mod runtime_types {
         pub heap: Vec<usize>,
         pub string_pool: Vec<usize>,
     }
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Clone)]
     pub struct Catches {
-        pub catches_ptr: usize,
-        pub cache: [Catch; CALL_STACK_SIZE],
+        pub cache: Vec<Catch>,
+        /// catch stack overflows when it reaches this size
+        pub max_depth: usize,
+        /// finally blocks that are currently running
+        pub pending: Vec<PendingFinally>,
+    }
+    /// finally block that is currently running
+    #[derive(Debug, Clone)]
+    pub struct PendingFinally {
+        /// size of the catch stack when the block started, catch blocks registered inside of it come after
+        pub depth: usize,
+        /// frame the block runs in
+        pub cs_ptr: usize,
+        pub exit: FinallyExit,
+    }
+    /// what happens when finally block reaches EndFinally
+    #[derive(Debug, Clone)]
+    pub enum FinallyExit {
+        /// block was left normally, continue at code ptr with registers
+        Resume(usize, Registers),
+        /// block was left by an exception, continue propagating it
+        Propagate(Exception),
     }
     impl Catches {
+        /// marks finally block just popped from the stack as running
+        pub fn start_finally(&mut self, cs_ptr: usize, exit: FinallyExit) {
+            self.abandon_finally();
+            self.pending.push(PendingFinally {
+                depth: self.cache.len(),
+                cs_ptr,
+                exit,
+            });
+        }
+        /// drops finally blocks that were running inside of the catch block just popped from the stack
+        pub fn abandon_finally(&mut self) {
+            let depth = self.cache.len();
+            self.pending.retain(|pending| pending.depth <= depth);
+        }
         /// pushes a new catch to the stack
         pub fn push(&mut self, catch: Catch) -> Result<(), ErrTypes> {
-            if self.catches_ptr == CALL_STACK_SIZE {
+            if self.cache.len() >= self.max_depth {
                 return Err(ErrTypes::CatchOwerflow);
             }
-            self.catches_ptr += 1;
-            self.cache[self.catches_ptr] = catch;
+            self.cache.push(catch);
             Ok(())
         }
         /// pops the last catch from the stack
         pub fn pop(&mut self) {
-            self.catches_ptr -= 1;
+            self.cache.pop();
         }
         /// truncates the stack to a given size
         pub fn truncate(&mut self, n: usize) {
-            self.catches_ptr = n;
+            self.cache.truncate(n);
         }
     }
     #[derive(Debug, Copy, Clone)]
@@ -1484,6 +2244,10 @@ pub mod runtime_types {
         pub code_ptr: usize,
         pub cs_ptr: usize,
         pub id: Option<usize>,
+        /// registers at the time the catch block was registered
+        pub reg_freeze: [Types; FREEZED_REG_SIZE],
+        /// runs on both normal exit and exception, never stops propagation
+        pub finally: bool,
     }
     /// indicates why program exited
     #[derive(Debug, Clone)]
@@ -1513,6 +2277,8 @@ pub mod runtime_types {
         Bool(bool),
         Pointer(usize, PointerTypes),
         Function(usize),
+        /// function ID, captured environment (location on heap)
+        Closure(usize, usize),
         // null represents an empty value
         Null,
         // void represents a value that is not meant to be used
@@ -1532,6 +2298,14 @@ pub mod runtime_types {
             }
             unreachable!()
         }
+        /// returns function ID and captured environment of functions and closures
+        pub fn as_fun(&self) -> Option<(usize, Option<usize>)> {
+            match *self {
+                Types::Function(id) => Some((id, None)),
+                Types::Closure(id, env) => Some((id, Some(env))),
+                _ => None,
+            }
+        }
         pub fn to_str(&self, mem: &Memory) -> String {
             match *self {
                 Types::Bool(b) => b.to_string(),
@@ -1549,6 +2323,7 @@ pub mod runtime_types {
                     PointerTypes::String => mem.strings.to_string(u_size),
                 },
                 Types::Function(val) => mem.fun_table[val].name.to_string(),
+                Types::Closure(val, _) => mem.fun_table[val].name.to_string(),
                 Types::Void => "void".to_string(),
             }
         }
@@ -1564,8 +2339,15 @@ pub mod runtime_types {
         pub kind: NonPrimitiveTypes,
         pub len: usize,
         pub pointers: usize,
+        /// indices of fields that can hold pointers or closures (header is index 0),
+        /// None if layout is unknown and every field has to be scanned by GC
+        ///
+        /// arrays can only list no fields, meaning their elements never hold pointers
+        pub pointer_fields: Option<Vec<usize>>,
         // first index is trait id, second is method id
         pub methods: HashMap<usize, Vec<usize>>,
+        /// ID of parent type, catch blocks for the parent also catch this type
+        pub parent: Option<usize>,
     }
     use std::{clone, fmt, rc::Rc, sync::Arc, hash::Hash, collections::HashMap};
 
@@ -1580,6 +2362,7 @@ pub mod runtime_types {
                     Types::Bool(_) => write!(f, "Bool"),
                     Types::Char(_) => write!(f, "Char"),
                     Types::Function(_) => write!(f, "CodePointer"),
+                    Types::Closure(_, _) => write!(f, "Closure"),
                     Types::Float(_) => write!(f, "Float"),
                     Types::Int(_) => write!(f, "Int"),
                     Types::Null => write!(f, "Null"),
@@ -1595,6 +2378,7 @@ pub mod runtime_types {
                     }
                     Types::Char(char) => write!(f, "Char<{char}>"),
                     Types::Function(loc) => write!(f, "CodePointer<{loc}>"),
+                    Types::Closure(loc, env) => write!(f, "Closure<{loc}, {env}>"),
                     Types::Float(num) => write!(f, "Float<{num}>"),
                     Types::Int(num) => write!(f, "Int<{num}>"),
                     Types::Null => write!(f, "Null"),
@@ -1608,6 +2392,7 @@ pub mod runtime_types {
                     Types::Bool(bol) => write!(f, "{bol}"),
                     Types::Char(char) => write!(f, "{char}"),
                     Types::Function(loc) => write!(f, "{loc}"),
+                    Types::Closure(loc, _) => write!(f, "{loc}"),
                     Types::Float(num) => write!(f, "{num}"),
                     Types::Int(num) => write!(f, "{num}"),
                     Types::Null => write!(f, "Null"),
@@ -1619,6 +2404,15 @@ pub mod runtime_types {
             }
         }
     }
+    impl NonPrimitiveType {
+        /// fields GC has to scan in objects of this type, None means all of them
+        pub fn scanned_fields(&self) -> Option<&[usize]> {
+            match (&self.kind, &self.pointer_fields) {
+                (NonPrimitiveTypes::Array, Some(fields)) if !fields.is_empty() => None,
+                (_, fields) => fields.as_deref(),
+            }
+        }
+    }
     impl fmt::Display for NonPrimitiveTypes {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match *self {
@@ -1694,11 +2488,11 @@ pub mod runtime_types {
         Dalc,
         /// Goto: pos | moves code_pointer to <pos>
         Goto(usize),
-        /// GotoCodePtr: pos_reg | moves code pointer to reg(<reg>)
+        /// GotoCodePtr: | moves code pointer to function or closure in reg(CODE_PTR_REG) and saves current code ptr
         Gotop,
         /// Branch: pos1 pos2 | if reg(0), goto <pos1> else goto <pos2>
         Brnc(usize, usize),
-        /// Return: | moves code_pointer to the last position in callstack and moves callstack back
+        /// Return: | moves code_pointer to the last position in callstack and moves callstack back; finally blocks of the frame run first
         Ret,
         /// Unfreeze | returns registers to their last freezed state
         Ufrz,
@@ -1733,7 +2527,7 @@ pub mod runtime_types {
         /// End              | terminates program
         End,
         //TODO: add to compiler
-        /// Cast: reg1 reg2 | casts value of reg1 to the type of reg2 and stores in reg1
+        /// Cast: reg1 reg2 | casts value of reg1 to the type of reg2 and stores in reg1; strings are parsed and anything can be cast into a string
         Cast(usize, usize),
         /// Length: reg | sets reg to Usize(size of an object)
         Len(usize),
@@ -1763,16 +2557,26 @@ pub mod runtime_types {
         Break(usize),
         /// Method: struct trait method | takes struct and calls method on it, assuming it implements trait  
         Mtd(usize, usize, usize),
-        /// Panic | program enters panic mode, returning from all stacks until exception is caught
+        /// Panic | throws value in reg(EXCEPTION_REG) as an exception, returning from all stacks until exception is caught
         Panic,
-        /// Catch | catches an error and returns program to normal mode, cached if read in normal mode
+        /// Catch | registers catch block, freezes registers and sets reg(CATCH_REG) to false; when an exception is caught, stack is unwound, registers are unfreezed and program continues after this instruction with reg(CATCH_REG) set to true and exception in reg(EXCEPTION_REG)
         Catch,
-        /// Catch ID: id | same as normal catch but responds only to exception with same id
+        /// Catch ID: id | same as normal catch but responds only to exception with same id or its subtypes, other exceptions propagate to outer catch blocks
         CatchId(usize),
-        /// Delete catch | deletes one catch instruction from cache
+        /// Delete catch | deletes one catch instruction from cache; if it is a finally block, runs it and continues after this instruction
         DelCatch,
+        /// Finally | registers finally block and sets reg(CATCH_REG) to false; when the block is deleted, its frame returns or an exception passes through, program continues after this instruction with reg(CATCH_REG) set to true
+        Finally,
+        /// End finally | ends finally block, either continuing after DelCatch, returning again or propagating the exception
+        EndFinally,
+        /// Throw: reg | throws value of reg(<reg>) as an exception and captures stack trace, catch blocks match on its non-primitive type
+        Throw(usize),
+        /// Trace | converts stack trace of the last exception to string and stores pointer in reg(POINTER_REG)
+        Trace,
         /// Non-primitive type: np_reg ID | compares reg(np_reg).id assuming it belongs to Non-primitive type with ID
         NPType(usize, usize),
+        /// Non-primitive subtype: np_reg ID | same as NPType but also true if reg(np_reg).id is a subtype of ID
+        NPSub(usize, usize),
         /// String new | creates new string and stores pointer in reg(POINTER_REGISTER)
         StrNew,
         /// Into string: val_reg | converts value on reg(value_reg) to string and stores pointer in reg(POINTER_REG)
@@ -1781,6 +2585,65 @@ pub mod runtime_types {
         ResD(usize),
         /// Argument dynamic: id_reg arg_num value_reg | pushes arguments to destination(stack or registers) based on fun_table(id_reg).params
         ArgD(usize, usize, usize),
+        /// Call function: fun_id | reserves frame based on fun_table(<fun_id>).stack_size, moves reg(0), reg(1), ... to its params, freezes registers and jumps to the function
+        Call(usize),
+        /// Call function dynamic: fun_reg | same as Call but takes function from reg(<fun_reg>)
+        CallDyn(usize),
+        /// Tail call: fun_id | same as Call but reuses current frame instead of pushing a new one
+        TailCall(usize),
+        /// Tail call dynamic: fun_reg | same as TailCall but takes function from reg(<fun_reg>)
+        TailCallDyn(usize),
+        /// Make closure: fun_reg env_reg | sets reg(fun_reg) to closure of function in reg(fun_reg) with environment object in reg(env_reg)
+        MkCls(usize, usize),
+        /// Captured: idx reg | reads value at <idx> of environment of the running closure into reg(<reg>)
+        Cpt(usize, usize),
+        /// Return from function | unfreezes registers, moves callstack back and returns after the call; finally blocks of the frame run first
+        Return,
+    }
+    impl Instructions {
+        /// registers read and written by instruction (reads, writes)
+        ///
+        /// registers changed by exceptions, finally blocks, native libraries and call arguments
+        /// are not known statically and are not included
+        pub fn reg_usage(&self) -> (Vec<usize>, Vec<usize>) {
+            use Instructions::*;
+            let freezed: Vec<usize> = (0..FREEZED_REG_SIZE).collect();
+            match *self {
+                Debug(reg) | Wr(_, reg) | Throw(reg) | Mtd(reg, _, _) | ResD(reg) | CallDyn(reg)
+                | TailCallDyn(reg) => (vec![reg], vec![]),
+                Rd(_, reg) | Rdc(_, reg) | Cpt(_, reg) => (vec![], vec![reg]),
+                Wrp(reg) | RAlc(reg) | TRng(reg, _) => (vec![POINTER_REG, reg], vec![]),
+                Rdp(reg) => (vec![POINTER_REG], vec![reg]),
+                Ptr(_) => (vec![], vec![GENERAL_REG1]),
+                Idx(reg) => (vec![POINTER_REG, reg], vec![POINTER_REG]),
+                IdxK(_) => (vec![POINTER_REG], vec![POINTER_REG]),
+                Alc(reg) | IntoStr(reg) => (vec![reg], vec![POINTER_REG]),
+                AlcS(_) | StrNew | Trace => (vec![], vec![POINTER_REG]),
+                Dalc => (vec![POINTER_REG], vec![]),
+                Gotop => (vec![CODE_PTR_REG], vec![]),
+                Brnc(_, _) => (vec![GENERAL_REG1], vec![]),
+                Ufrz | Return => (vec![], freezed),
+                Frz => (freezed, vec![]),
+                Catch | CatchId(_) | Finally => (freezed, vec![CATCH_REG]),
+                Swap(reg1, reg2) => (vec![reg1, reg2], vec![reg1, reg2]),
+                Add(reg1, reg2, res) | Sub(reg1, reg2, res) | Mul(reg1, reg2, res)
+                | Div(reg1, reg2, res) | Mod(reg1, reg2, res) | Equ(reg1, reg2, res)
+                | Grt(reg1, reg2, res) | Less(reg1, reg2, res) | And(reg1, reg2, res)
+                | Or(reg1, reg2, res) => (vec![reg1, reg2], vec![res]),
+                Not(reg, res) | Move(reg, res) => (vec![reg], vec![res]),
+                Cast(reg1, reg2) => (vec![reg1, reg2], vec![reg1]),
+                Type(reg1, reg2) => (vec![reg1, reg2], vec![reg2]),
+                Len(reg) => (vec![reg], vec![reg]),
+                CpRng(reg1, reg2, _) => (vec![reg1, reg2], vec![]),
+                Cal(_, _) => (vec![], vec![RETURN_REG]),
+                Panic => (vec![EXCEPTION_REG], vec![]),
+                NPType(reg, _) | NPSub(reg, _) => (vec![reg], vec![GENERAL_REG3]),
+                ArgD(id_reg, _, value_reg) => (vec![id_reg, value_reg], vec![]),
+                MkCls(fun_reg, env_reg) => (vec![fun_reg, env_reg], vec![fun_reg]),
+                Goto(_) | Jump(_) | Ret | Back | Res(_, _) | End | Sweep | SweepUnoptimized
+                | Break(_) | DelCatch | EndFinally | Call(_) | TailCall(_) => (vec![], vec![]),
+            }
+        }
     }
     impl fmt::Display for Instructions {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1834,13 +2697,25 @@ pub mod runtime_types {
                 Instructions::Catch => "Catch",
                 Instructions::CatchId(_) => "Catch",
                 Instructions::DelCatch => "DeleteCatch",
+                Instructions::Throw(_) => "Throw",
+                Instructions::Trace => "Trace",
+                Instructions::Finally => "Finally",
+                Instructions::EndFinally => "EndFinally",
                 Instructions::NPType(_, _) => "NonPrimitiveType",
+                Instructions::NPSub(_, _) => "NonPrimitiveSubtype",
                 Instructions::StrNew => "StringNew",
                 //Instructions::StrCpy(_) => "StringCopy",
                 Instructions::Dalc => "Deallocate",
                 Instructions::IntoStr(_) => "IntoString",
                 Instructions::ResD(_) => "ReserveDynamic",
                 Instructions::ArgD(_, _, _) => "ArgumentDynamic",
+                Instructions::Call(_) => "FunctionCall",
+                Instructions::CallDyn(_) => "FunctionCallDyn",
+                Instructions::Return => "FunctionReturn",
+                Instructions::TailCall(_) => "TailCall",
+                Instructions::TailCallDyn(_) => "TailCallDyn",
+                Instructions::MkCls(_, _) => "MakeClosure",
+                Instructions::Cpt(_, _) => "Captured",
             };
             write!(f, "{str}")
         }
@@ -1852,6 +2727,22 @@ pub mod runtime_types {
         pub end: usize,
         pub code_ptr: usize,
         pub pointers_len: usize,
+        /// environment of the running closure
+        pub env: Option<usize>,
+        /// frame was entered by a call, code_ptr is the return address
+        pub call: bool,
+    }
+    impl CallStack {
+        pub fn new() -> Self {
+            Self {
+                reg_freeze: [Types::Null; FREEZED_REG_SIZE],
+                end: 0,
+                code_ptr: 0,
+                pointers_len: 0,
+                env: None,
+                call: false,
+            }
+        }
     }
 }
 pub mod runtime_error {
@@ -1867,6 +2758,13 @@ pub mod runtime_error {
         CatchOwerflow,
         MethodNotFound,
         Message(String),
+        /// text that could not be parsed, type it was parsed into
+        ParseError(String, Types),
+        InvalidChar(Types),
+        /// Return or Ret outside of any frame
+        StackUnderflow,
+        /// ID of function that is not in fun_table
+        InvalidFunction(usize),
     }
     fn gen_message(header: String, line: Option<(usize, usize)>, err_no: u8) -> String {
         return if let Some(line) = line {
@@ -1876,8 +2774,61 @@ pub mod runtime_error {
             format!("\x1b[90mErr{err_no:03}\x1b[0m \x1b[91m{header}\x1b[0m\n\x1b[90mLocation unspecified.\x1b[0m")
         };
     }
+    /// non-primitive IDs of runtime errors start here, ID of each error is RUNTIME_ERR_ID + its code
+    ///
+    /// user defined non-primitive types must have lower IDs
+    pub const RUNTIME_ERR_ID: usize = 1 << 24;
+    /// name of error with given code, exceptions made from errors have no entry in non_primitives
+    pub fn error_name(code: usize) -> Option<&'static str> {
+        let name = match code {
+            0 => "CrossTypeOperation",
+            1 => "WrongTypeOperation",
+            2 => "InvalidType",
+            3 => "Expected",
+            4 => "ImplicitCast",
+            5 => "StackOverflow",
+            6 => "CatchOwerflow",
+            7 => "MethodNotFound",
+            8 => "Message",
+            9 => "ParseError",
+            10 => "InvalidChar",
+            11 => "StackUnderflow",
+            12 => "InvalidFunction",
+            _ => return None,
+        };
+        Some(name)
+    }
+    impl ErrTypes {
+        /// stable error code, same as ErrNNN in error messages
+        pub fn code(&self) -> u8 {
+            describe(self).1
+        }
+        /// error message without location
+        pub fn message(&self) -> String {
+            describe(self).0
+        }
+        /// non-primitive ID of exceptions made from this error
+        pub fn id(&self) -> usize {
+            RUNTIME_ERR_ID + self.code() as usize
+        }
+    }
+    /// same as gen_message, but followed by backtrace instead of a single location
+    fn gen_message_trace(header: String, trace: &str, err_no: u8) -> String {
+        format!("\x1b[90mErr{err_no:03}\x1b[0m \x1b[91m{header}\x1b[0m\n\x1b[90m{trace}\x1b[0m")
+    }
+    /// error message with backtrace formatted by Context::format_trace
+    pub fn get_message_trace(kind: &ErrTypes, trace: &str) -> String {
+        let data = describe(kind);
+        gen_message_trace(data.0, trace, data.1)
+    }
     pub fn get_message(kind: &ErrTypes, line: Option<(usize, usize)>) -> String {
-        let data = match &kind {
+        let data = describe(kind);
+        let message = gen_message(data.0, line, data.1);
+        message
+    }
+    /// returns message and code of error
+    fn describe(kind: &ErrTypes) -> (String, u8) {
+        match &kind {
             ErrTypes::CrossTypeOperation(var1, var2, instr) => (
                 format!("Operation '{instr}' failed: Cross-type operation {var1:+}, {var2:+}"),
                 0,
@@ -1901,9 +2852,17 @@ pub mod runtime_error {
             ErrTypes::CatchOwerflow => (format!("Catch overflow"), 6),
             ErrTypes::MethodNotFound => (format!("Method not found"), 7),
             ErrTypes::Message(msg) => (msg.clone(), 8),
-        };
-        let message = gen_message(data.0, line, data.1);
-        message
+            ErrTypes::ParseError(text, typ) => (
+                format!("Parse error: Can not parse \"{text}\" into type {typ:#}"),
+                9,
+            ),
+            ErrTypes::InvalidChar(value) => (
+                format!("Cast error: {value:+} is not a valid character code"),
+                10,
+            ),
+            ErrTypes::StackUnderflow => (format!("Stack underflow: Return from the base frame"), 11),
+            ErrTypes::InvalidFunction(id) => (format!("Function with ID {id} does not exist"), 12),
+        }
     }
 }
 pub mod lib {
@@ -1965,5 +2924,17 @@ pub mod lib {
         ///
         /// my_id: the id of the library in case you have a funtion that takes a struct as an argument and you need to check type on runtime
         fn register(&self) -> RegisterData;
+        /// returns state of the library to be stored in a checkpoint
+        ///
+        /// libraries holding state that can not be saved (open files, sockets, ...) should return Err with the reason,
+        /// which refuses the checkpoint
+        fn save_state(&self) -> Result<Vec<u8>, String> {
+            Ok(Vec::new())
+        }
+        /// restores state returned by save_state when the VM resumes from a checkpoint
+        fn restore_state(&mut self, state: &[u8]) -> Result<(), String> {
+            let _ = state;
+            Ok(())
+        }
     }
 }
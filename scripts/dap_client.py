@@ -0,0 +1,112 @@
+#!/usr/bin/env python3
+"""Scripted Debug Adapter Protocol client for testing `rusty_vm --dap`.
+
+Runs test scenario 17 (uncaught runtime error with debug info), sets a breakpoint
+on function `add`, inspects the stack and variables and steps to the exception.
+
+usage: scripts/dap_client.py [path to rusty_vm]
+"""
+import json
+import os
+import subprocess
+import sys
+
+vm = sys.argv[1] if len(sys.argv) > 1 else "target/debug/rusty_vm"
+# without RUDA_PATH libraries of a program can not be found
+env = {key: value for key, value in os.environ.items() if key != "RUDA_PATH"}
+proc = subprocess.Popen([vm, "--dap"], stdin=subprocess.PIPE, stdout=subprocess.PIPE, env=env)
+# above 2^53, adapter must echo it back exactly
+seq = 2**60
+pending = []
+
+
+def send(command, arguments=None):
+    global seq
+    seq += 1
+    msg = {"seq": seq, "type": "request", "command": command}
+    if arguments is not None:
+        msg["arguments"] = arguments
+    body = json.dumps(msg).encode()
+    proc.stdin.write(b"Content-Length: %d\r\n\r\n" % len(body) + body)
+    proc.stdin.flush()
+    return seq
+
+
+def read():
+    length = None
+    while True:
+        line = proc.stdout.readline()
+        if not line:
+            raise EOFError("adapter closed stdout")
+        line = line.strip()
+        if not line:
+            break
+        if line.startswith(b"Content-Length:"):
+            length = int(line.split(b":")[1])
+    msg = json.loads(proc.stdout.read(length))
+    print("<-", json.dumps(msg))
+    return msg
+
+
+def wait(kind, name):
+    """returns first message of kind with command or event name, keeps the others"""
+    for msg in pending:
+        if msg["type"] == kind and msg.get("command", msg.get("event")) == name:
+            pending.remove(msg)
+            return msg
+    while True:
+        msg = read()
+        if msg["type"] == kind and msg.get("command", msg.get("event")) == name:
+            return msg
+        pending.append(msg)
+
+
+def request(command, arguments=None, success=True):
+    req = send(command, arguments)
+    res = wait("response", command)
+    assert res["request_seq"] == req, res
+    assert res["success"] == success, res
+    return res.get("body", {}) if success else res["message"]
+
+
+caps = request("initialize", {"adapterID": "ruda"})
+assert caps["supportsConfigurationDoneRequest"]
+message = request("launch", {"program": __file__}, success=False)
+assert "RUDA_PATH" in message, message
+request("launch", {"test": 17})
+wait("event", "initialized")
+bps = request("setFunctionBreakpoints", {"breakpoints": [{"name": "add"}]})
+assert bps["breakpoints"][0]["verified"]
+request("configurationDone")
+assert "not supported" in request("pause", {"threadId": 1}, success=False)
+
+stopped = wait("event", "stopped")
+assert stopped["body"]["reason"] == "breakpoint", stopped
+frames = request("stackTrace", {"threadId": 1})["stackFrames"]
+assert [f["name"] for f in frames] == ["add", "<main>"], frames
+assert frames[0]["source"]["name"] == "main.rd" and frames[0]["line"] == 1, frames
+assert frames[1]["line"] == 5 and frames[1]["column"] == 12, frames
+
+scopes = request("scopes", {"frameId": 0})["scopes"]
+locals_ref = next(s for s in scopes if s["name"] == "Locals")["variablesReference"]
+variables = request("variables", {"variablesReference": locals_ref})["variables"]
+assert [(v["name"], v["value"]) for v in variables] == [("a", "Int<5>"), ("b", "Bool<true>")], variables
+
+# step over line 1 of add, stops on line 2
+request("next", {"threadId": 1})
+wait("event", "stopped")
+frames = request("stackTrace", {"threadId": 1})["stackFrames"]
+assert frames[0]["line"] == 2, frames
+
+request("continue", {"threadId": 1})
+stopped = wait("event", "stopped")
+assert stopped["body"]["reason"] == "exception", stopped
+assert "Cross-type operation" in stopped["body"]["text"], stopped
+
+request("continue", {"threadId": 1})
+exited = wait("event", "exited")
+assert exited["body"]["exitCode"] == 1, exited
+wait("event", "terminated")
+request("disconnect")
+proc.wait(timeout=5)
+print("ok")
@@ -0,0 +1,307 @@
+#!/usr/bin/env python3
+"""Runs test scenarios of `rusty_vm --test <id>` in every execution mode and checks their output.
+
+Scenarios are run in a temporary directory, so test.rdbin and other written files
+do not end up in the repository.
+
+usage: scripts/run_tests.py [path to rusty_vm]
+"""
+import json
+import os
+import subprocess
+import sys
+import tempfile
+
+vm = os.path.abspath(sys.argv[1] if len(sys.argv) > 1 else "target/debug/rusty_vm")
+workdir = tempfile.mkdtemp(prefix="rusty_vm_tests_")
+failures = []
+
+
+def run(test, *args, stdin=""):
+    """output of scenario, stdout and stderr together"""
+    proc = subprocess.run(
+        [vm, "--test", str(test), *args],
+        input=stdin.encode(),
+        stdout=subprocess.PIPE,
+        stderr=subprocess.STDOUT,
+        cwd=workdir,
+        timeout=60,
+    )
+    return proc.stdout.decode()
+
+
+def path(name):
+    return os.path.join(workdir, name)
+
+
+def check(name, condition, output=""):
+    if condition:
+        print("ok", name)
+        return
+    print("FAIL", name)
+    print(output)
+    failures.append(name)
+
+
+# debugger: breakpoint on function, registers, stack and finish
+out = run(12, "--debug", stdin="b factorial\nc\nr 0\nst\nf\nq\n")
+check("debugger breakpoint", "Breakpoint set at 5: Rd(1, 0) in factorial" in out and "Breakpoint 5\n5: Rd(1, 0) in factorial" in out, out)
+check("debugger registers", "reg(0) = Int<10>" in out, out)
+check("debugger stack", "Frame 2 (2..3)\n[1] Int<10>" in out, out)
+check("debugger finish stops at breakpoint of recursive call", out.count("Breakpoint 5\n") == 2, out)
+out = run(12, "--debug", stdin="c\nc\n")
+check("debugger runs to end", "Int<3628800>" in out and "Program ended with End" in out and "Program has ended." in out, out)
+
+# reverse execution: native call stepped over again returns its recorded result instead of a new time
+out = run(18, "--debug", stdin="s\ns\nrs\nrs\ns\ns\nq\n")
+times = [line.split("Int<")[1] for line in out.splitlines() if "Int<" in line]
+check("native call is not called again after going back", len(times) == 2 and times[0] == times[1], out)
+
+
+def reload(test, *args):
+    """output of scenario loaded back from test.rdbin it wrote, without native calls to replay"""
+    run(test, "--record", path("empty.rec"))
+    return run(test, path("test.rdbin"), "--replay", path("empty.rec"), *args)
+
+
+def chars(text):
+    """string as it is shown in the post-process report"""
+    return "[" + ", ".join("'\\''" if c == "'" else f"'{c}'" for c in text) + "]"
+
+
+# record and replay: results of native calls and strings they allocated come from the recording
+out = run(18, "--record", path("clock.rec"))
+replayed = run(18, "--replay", path("clock.rec"))
+check("replay of recorded native calls", out == replayed and chars("now: ")[:-1] in out, [out, replayed])
+replayed = run(18, path("test.rdbin"), "--replay", path("clock.rec"), "--report")
+check("replay without libraries of the binary", replayed == out.removeprefix("Running test 18\n"), [out, replayed])
+
+# subtypes of non-primitive types, parent types kept by the binary format
+out = run(31)
+check("NPSub and catch block of parent type", "Bool<true>\nBool<false>\nBool<false>\nNon-primitive<1>\n\n" in out, out)
+out = reload(31)
+check("parent types after loading binary", out.startswith("Bool<true>\nBool<false>\nBool<false>\nNon-primitive<1>\n"), out)
+
+# binary written before the format had a version, its types have no pointer fields and no parent
+def number(value, size=8):
+    return "".join(chr(byte) for byte in value.to_bytes(size, "big"))
+
+
+code = chr(16) + number(1, 4) + number(0, 4) + chr(5) + number(0, 4) + chr(0) + chr(0) + chr(0) + chr(30)
+point = chr(1) + number(2) + number(5) + "Point" + number(0) + number(0)
+with open(path("legacy.rdbin"), "w") as file:
+    file.write("RUDA" + number(4) + code + number(1) + chr(9) + number(0) + number(0) + number(1) + point + number(0) + number(0))
+run(1, "--record", path("empty.rec"))
+out = run(1, path("legacy.rdbin"), "--replay", path("empty.rec"))
+check("binary without format version", out.startswith("Non-primitive<0>\n"), out)
+
+# casts between numbers, chars and strings, failed casts caught as runtime errors
+out = run(21)
+check("casts", "Char<A>\nChar<\u263a>\nChar<B>\nInt<97>\nUsize<97>\nFloat<97>\nInt<42>\nFloat<2.5>\nBool<true>\n" in out, out)
+check("InvalidChar and ParseError caught with their codes", "Usize<10>\nUsize<9>\n" in out, out)
+check("message of caught ParseError", chars('Parse error: Can not parse "forty two" into type Int')[1:-1] in out, out)
+
+# Return from the base frame and Call of missing function
+out = run(22)
+check("StackUnderflow and InvalidFunction caught with their codes", "Usize<11>\nUsize<12>\n" in out, out)
+
+# tail call from the base frame, catch blocks dropped by tail call
+out = run(23)
+check("tail call from base frame and dropped catch", "Int<42>\nUsize<12>\n" in out, out)
+out = run(30)
+check("tail call from a block reuses frame of the function", "Running test 30\nInt<5>\nInt<7>\nInt<5>\nInt<5>\n\n" in out, out)
+
+# recursion limits
+out = run(24)
+check("recursion within limits", "Int<50>\nInt<50>\n" in out, out)
+out = run(24, "--max-call-depth", "40")
+check("StackOverflow at --max-call-depth", "Usize<5>\n" in out, out)
+out = run(24, "--max-catch-depth", "20")
+check("CatchOwerflow at --max-catch-depth", "Int<50>\nUsize<6>\n" in out, out)
+
+# closure frame reused by a plain block
+out = run(25)
+check("closure environment is not kept by reused frame", "Int<15>\nUsize<1>\n" in out, out)
+
+# stack trace of uncaught error lists only frames entered by calls
+out = run(17)
+check("stack trace without block frames", "at add (main.rd:2:12)\nat <main> (main.rd:5:12)\x1b[0m\n" in out, out)
+
+# runtime error caught in the caller, code and message fields of the exception
+out = run(26)
+check("caught runtime error has code", "Usize<0>\n" in out and "[NonPrimitive(16777216), Usize(0), Pointer(" in out, out)
+check("frames unwound to the catch block", "Usize<0>\nInt<7>\n" in out and "Stack: [Int(7), Bool(true), Null, Null]" in out, out)
+check("caught runtime error has message", chars("Operation 'Addition' failed: Cross-type operation Int<7>, Bool<true>") in out, out)
+
+# catch block is dropped when its function returns
+out = run(28)
+check("returned function does not catch later exceptions", "Int<1>\nInt<2>\n" in out and "Int<3>" not in out, out)
+
+# finally blocks
+out = run(15, "--report")
+check("exception caught by parent type", "Running test 15\nPointer<0, Object>\nInt<42>\n" in out, out)
+check("trace of caught exception", "Strings: [" + chars("at fails (23)")[:-1] + ", '\\n', " + chars("at <main> (4)")[1:] + "]" in out and "Pointer(0, String)" in out.split("Registers: ")[1], out)
+out = run(16)
+check("finally block runs before exception reaches the caller", "Running test 16\nBool<true>\nInt<1>\n" in out, out)
+out = run(29)
+check("finally block left by DelCatch and by Return", "Running test 29\nBool<true>\nInt<3>\nBool<true>\nInt<4>\n" in out, out)
+check("exception caught inside finally block does not replace the propagated one", "Int<2>\n\x1b[91mUncaught exception Int<1>\x1b[0m\n\x1b[90mat rethrows (24)\n" in out, out)
+run(29, "--checkpoint", path("finally"), "--checkpoint-every", "28")
+out = run(29, "--resume", path("finally"))
+check("propagated exception kept by checkpoint", out.startswith("Int<2>\n\x1b[91mUncaught exception Int<1>\x1b[0m\n\x1b[90mat rethrows (24)\n"), out)
+
+# GC roots are constants and frames that did not return
+out = run(27)
+check("object of returned frame is freed", "Pointer<0, Object>\n" in out, out)
+out = run(19, "--alloc-timeline", path("timeline19.csv"))
+with open(path("timeline19.csv")) as file:
+    rows = file.read().splitlines()
+check("unreachable cycle freed, list of million objects marked without overflow", "Int<1>\n" in out and rows[-1].split(",")[1] == "1000000", [out, rows])
+out = run(20, "--report-format", "json", "--report-output", path("report.json"))
+with open(path("report.json")) as file:
+    heap = json.load(file)["heap"]
+check("only pointer fields are scanned", "Int<499999>\n" in out and [obj["idx"] for obj in heap] == [0, 1], [out, [obj["idx"] for obj in heap]])
+out = run(8, "--report")
+check("constants below frames stay alive", chars("BMW") in out and chars("Yamaha") in out, out)
+
+# watchpoints: logged from command line, stopping in debugger
+out = run(14, "--watch", "heap:0:0")
+check("watchpoint logs allocation and write", "heap[0][0]: <none> -> Null at 1" in out and "heap[0][0]: Null -> Int<5> at 5" in out, out)
+out = run(13, "--watch", "stack:4")
+check("watchpoint sees arguments passed by tail call", out.count("Watchpoint 0 stack[4]") == 10001 and "Int<10000> -> Int<19999> at 17" in out, out)
+out = run(26, "--watch", "stack:3")
+check("watchpoint sees stack cleared by unwinding", "stack[3]: Bool<true> -> Null at 17" in out, out)
+out = run(14, "--debug", stdin="watch heap 0 0\nc\nc\n")
+check("debugger stops on watchpoint", "Watchpoint 0 heap[0][0]: <none> -> Null at 1\n2: Move(6, 1) in <main>" in out and "Watchpoint 0 heap[0][0]: Null -> Int<5> at 5\n6: Rdc(0, 2) in <main>" in out, out)
+
+# trace as JSON Lines, every line is an executed instruction
+def trace(*args):
+    run(12, "--trace", path("trace.jsonl"), *args)
+    with open(path("trace.jsonl")) as file:
+        return [json.loads(line) for line in file]
+
+
+lines = trace()
+check("trace has every instruction", len(lines) == 101 and lines[-1]["instr"] == "End", lines[-3:])
+check("trace records registers", lines[1] == {"ptr": 1, "instr": "Rdc(0, 0)", "fun": "<main>", "depth": 1, "reads": [], "writes": [{"reg": 0, "value": "Int(10)"}]}, lines[:3])
+lines = trace("--trace-fun", "factorial", "--trace-op", "Mul")
+check("trace filtered by function and kind", len(lines) == 9 and all(line["fun"] == "factorial" and line["instr"].startswith("Mul") for line in lines), lines)
+check("trace of last multiplication", lines[-1]["writes"] == [{"reg": 7, "value": "Int(3628800)"}], lines[-1:])
+lines = trace("--trace-range", "0..3")
+check("trace filtered by range", [line["ptr"] for line in lines] == [0, 1, 2], lines)
+
+# profile, times differ between runs so only counts are checked
+def profile_rows(out, section):
+    """count and name of rows in section of profile"""
+    rows = out.split(section + "\n", 1)[1].split("\n\n", 1)[0].splitlines()[1:]
+    return {row.split("%  ", 1)[1]: int(row.split()[0]) for row in rows}
+
+
+out = run(12, "--profile")
+check("profile of functions", profile_rows(out, "Functions (self time)") == {"<main>": 5, "factorial": 96}, out)
+check("profile of instruction kinds", profile_rows(out, "Instructions by kind")["Call"] == 10, out)
+check("profile of instructions", profile_rows(out, "Instructions by index")["15: Mul(0, 7, 7) in factorial"] == 9, out)
+check("profile of GC", "GC: 101 sweeps" in out, out)
+out = run(18, "--profile")
+check("profile of native functions", profile_rows(out, "Native functions") == {"clock::0": 1, "clock::1": 1}, out)
+run(12, "--profile-folded", path("folded.txt"))
+with open(path("folded.txt")) as file:
+    stacks = [line.rsplit(" ", 1)[0] for line in file]
+check("folded stacks", stacks == ["<main>"] + ["<main>" + ";factorial" * depth for depth in range(1, 11)], stacks)
+
+# lcov coverage, lines are instructions when there is no debug info
+def coverage(test):
+    run(test, "--coverage", path("coverage.info"))
+    with open(path("coverage.info")) as file:
+        return file.read().splitlines()
+
+
+out = run(12, "--coverage", path("coverage.info"))
+check("coverage says lines are instructions", "Program has no debug info, line N of coverage is instruction N - 1" in out, out)
+lines = coverage(12)
+check("coverage of function", ["FN:6,factorial", "FNDA:10,factorial", "FNF:1", "FNH:1"] == lines[2:6], lines)
+check("coverage of branches", ["BRDA:9,8,0,9", "BRDA:9,8,1,1", "BRF:2", "BRH:2"] == lines[6:10], lines)
+check("coverage of lines", "DA:6,10" in lines and "DA:17,9" in lines and lines[-3:] == ["LF:17", "LH:17", "end_of_record"], lines)
+lines = coverage(21)
+check("coverage of instructions that never ran", ["DA:42,0", "DA:43,0", "DA:54,0", "DA:55,0"] == [line for line in lines if line.endswith(",0")] and "LH:56" in lines, lines)
+lines = coverage(17)
+check("coverage with debug info", lines[1] == "SF:main.rd" and ["DA:1,1", "DA:2,1", "DA:5,1", "LF:3", "LH:3"] == lines[-6:-1], lines)
+# debug info survives writing the binary and reading it back
+out = reload(17, "--coverage", path("reloaded.info"))
+with open(path("reloaded.info")) as file:
+    check("coverage with debug info of loaded binary", file.read().splitlines() == lines, out)
+check("loaded binary has debug info", "no debug info" not in out, out)
+out = reload(17)
+check("stack trace of loaded binary", "at add (main.rd:2:12)\nat <main> (main.rd:5:12)\x1b[0m\n" in out, out)
+
+# allocation profile and timeline
+out = run(25, "--alloc-profile")
+check("allocation summary", "4 allocations, 364 bytes, 2 alive at exit, 39 sweeps\nPeak heap: 2 objects, 96 bytes at instruction 30" in out, out)
+check("allocation sites", "1         24        0         20.0  1: AlcS(1) in <main> (object)" in out and "1        244        1          8.0  25: Cpt(0, 0) in <main> (string)" in out, out)
+check("longest retained", "20       20         24  object 0 from 1: AlcS(1) in <main>\n" in out, out)
+run(25, "--alloc-timeline", path("timeline.csv"))
+with open(path("timeline.csv")) as file:
+    rows = file.read().splitlines()
+check("allocation timeline", rows == [
+    "instruction,objects,heap_bytes,strings,string_bytes",
+    "0,0,0,0,0",
+    "2,1,24,0,0",
+    "22,0,0,0,0",
+    "26,1,24,0,0",
+    "30,2,96,1,244",
+    "30,1,72,1,244",
+], rows)
+
+# machine-readable report and heap graph
+out = run(25, "--report-format", "json")
+report = json.loads(out.strip().splitlines()[-1])
+check("json report names runtime error objects", report["heap"] == [{"idx": 1, "type": "WrongTypeOperation", "values": ["NonPrimitive(16777217)", "Usize(1)", "Pointer(0, String)"]}], out)
+check("json report of exit", report["exit"] == {"kind": "end", "code": 0, "message": None}, out)
+run(25, "--heap-dot", path("heap.dot"))
+with open(path("heap.dot")) as file:
+    dot = file.read()
+check("heap graph edges of field pointers", "registers:f6 -> obj1:f1;" in dot and "registers:f7 -> obj1;" in dot and "obj1:f2 -> str0;" in dot, dot)
+check("heap graph names runtime error objects", 'obj1 [label="1: WrongTypeOperation|' in dot, dot)
+run(14, "--heap-dot", path("heap.dot"))
+with open(path("heap.dot")) as file:
+    dot = file.read()
+check("heap graph edges of closures", "registers:f2 -> obj0;" in dot and "registers:f8 -> obj0;" in dot, dot)
+
+# flags of different modes can not be used together
+out = run(12, "--trace", path("trace.jsonl"), "--profile")
+check("trace conflicts with profile", "the argument '--trace <TRACE>' cannot be used with '--profile'" in out, out)
+out = run(12, "--debug", "--alloc-profile")
+check("debugger conflicts with analysis flags", "the argument '--debug' cannot be used with '--alloc-profile'" in out, out)
+out = run(12, "--trace-op", "Add")
+check("trace filter requires trace", "--trace <TRACE>" in out and "required" in out, out)
+
+# checkpoints
+expected = run(12, "--report")
+run(12, "--checkpoint", path("checkpoint"), "--checkpoint-every", "50")
+out = run(12, "--resume", path("checkpoint"), "--report")
+check("resume from checkpoint", out.split("Post-process")[1] == expected.split("Post-process")[1], out)
+with open(path("checkpoint"), "rb") as file:
+    checkpoint = file.read()
+with open(path("truncated"), "wb") as file:
+    file.write(checkpoint[: len(checkpoint) // 2])
+out = run(12, "--resume", path("truncated"))
+check("resume from truncated checkpoint", f"Failed to resume from '{path('truncated')}': Unexpected end of file" in out, out)
+# lengths are not trusted, a huge count of instructions must not be allocated up front
+text = checkpoint.decode()
+start = len("RUDACHK" "RUDA") + 1
+with open(path("huge"), "w", newline="") as file:
+    file.write(text[:start] + number(1 << 60) + text[start + 8 :])
+out = run(12, "--resume", path("huge"))
+check("resume from checkpoint with huge length", f"Failed to resume from '{path('huge')}': Unknown instruction" in out, out)
+# stack pointer, globals and count of frames
+frames = number(1) + number(2) + number(12)
+start = text.index(frames)
+with open(path("frame"), "w", newline="") as file:
+    file.write(text[:start] + number(1000) + text[start + 8 :])
+out = run(12, "--resume", path("frame"))
+check("resume from checkpoint with invalid frame", "Checkpoint points to frame 1000, but has only 12 frames" in out, out)
+
+if failures:
+    print(f"{len(failures)} failed: {', '.join(failures)}")
+    sys.exit(1)
+print("all passed")
@@ -0,0 +1,457 @@
+//! Allocation profiler, enabled with --alloc-profile
+//!
+//! Allocations are found by comparing heap and string pool before and after every instruction,
+//! so objects made by libraries and runtime errors are tracked the same way as Alc or StrNew.
+//! Object sizes are in bytes of their values (Types for objects, chars for strings), they are
+//! measured when a value is allocated, reallocated or reached through registers after a change.
+//! Timeline of heap and string pool sizes can be written as CSV with --alloc-timeline <file>.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+    mem::size_of,
+};
+
+use runtime::runtime_types::{Context, Instructions, PointerTypes, Types, POINTER_REG};
+
+use crate::debugger;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pool {
+    Heap,
+    String,
+}
+
+impl fmt::Display for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pool::Heap => write!(f, "object"),
+            Pool::String => write!(f, "string"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub pool: Pool,
+    /// index in heap or string pool
+    pub idx: usize,
+    /// instruction that allocated it
+    pub site: usize,
+    pub size: usize,
+    /// executed instructions at allocation
+    pub born: usize,
+    /// executed instructions at deallocation, None if still alive
+    pub freed: Option<usize>,
+    /// sweeps survived
+    pub sweeps: usize,
+}
+
+/// sizes of memory at some point of execution
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// executed instructions
+    pub time: usize,
+    pub objects: usize,
+    pub heap_bytes: usize,
+    pub strings: usize,
+    pub string_bytes: usize,
+}
+
+#[derive(Default)]
+pub struct Allocations {
+    pub allocations: Vec<Allocation>,
+    /// sampled whenever heap or string pool changes
+    pub timeline: Vec<Sample>,
+    /// executed instructions
+    pub total: usize,
+    /// reallocations per instruction index (count, bytes)
+    pub reallocations: HashMap<usize, (usize, usize)>,
+}
+
+/// last seen state of one pool
+struct PoolState {
+    pool: Pool,
+    len: usize,
+    garbage: Vec<usize>,
+    /// same indices as garbage
+    free: HashSet<usize>,
+    /// size of every live value when it was last measured, 0 for free ones
+    sizes: Vec<usize>,
+    /// number of live values and sum of their sizes
+    count: usize,
+    bytes: usize,
+    /// pool index -> index in allocations
+    live: HashMap<usize, usize>,
+}
+
+impl PoolState {
+    fn new(pool: Pool, ctx: &Context) -> Self {
+        let mut state = Self {
+            pool,
+            len: 0,
+            garbage: Vec::new(),
+            free: HashSet::new(),
+            sizes: Vec::new(),
+            count: 0,
+            bytes: 0,
+            live: HashMap::new(),
+        };
+        let (len, garbage) = state.pool_of(ctx);
+        state.garbage = garbage.to_vec();
+        state.free = garbage.iter().copied().collect();
+        state.len = len;
+        state.sizes = vec![0; len];
+        for idx in 0..len {
+            if state.alive(idx) {
+                state.set_size(idx, measure(ctx, pool, idx));
+                state.count += 1;
+            }
+        }
+        state
+    }
+
+    fn pool_of<'a>(&self, ctx: &'a Context) -> (usize, &'a Vec<usize>) {
+        let mem = &ctx.memory;
+        match self.pool {
+            Pool::Heap => (mem.heap.data.len(), &mem.heap.garbage),
+            Pool::String => (mem.strings.pool.len(), &mem.strings.garbage),
+        }
+    }
+
+    fn alive(&self, idx: usize) -> bool {
+        idx < self.len && !self.free.contains(&idx)
+    }
+
+    /// replaces size of live value in totals
+    fn set_size(&mut self, idx: usize, size: usize) {
+        self.bytes = self.bytes - self.sizes[idx] + size;
+        self.sizes[idx] = size;
+    }
+
+    /// catches up with the pool, returns indices that stopped and started being alive
+    ///
+    /// between sweeps garbage is only pushed to and popped from, so only indices after
+    /// the common start of old and new garbage and indices added or removed at the end
+    /// of the pool can change
+    fn update(&mut self, ctx: &Context, swept: bool) -> (Vec<usize>, Vec<usize>) {
+        let (len, garbage) = self.pool_of(ctx);
+        let start = match swept {
+            true => 0,
+            false => {
+                let mut start = self.garbage.len().min(garbage.len());
+                while start > 0 && self.garbage[start - 1] != garbage[start - 1] {
+                    start -= 1;
+                }
+                start
+            }
+        };
+        let mut candidates: Vec<usize> = match swept {
+            true => (0..self.len.max(len)).collect(),
+            false => self.garbage[start..]
+                .iter()
+                .chain(garbage[start..].iter())
+                .copied()
+                .chain(len.min(self.len)..len.max(self.len))
+                .collect(),
+        };
+        candidates.sort_unstable();
+        candidates.dedup();
+        let was_alive: Vec<bool> = candidates.iter().map(|idx| self.alive(*idx)).collect();
+        for idx in self.garbage.drain(start..) {
+            self.free.remove(&idx);
+        }
+        self.garbage.extend_from_slice(&garbage[start..]);
+        self.free.extend(garbage[start..].iter().copied());
+        self.len = len;
+        self.sizes.resize(self.sizes.len().max(len), 0);
+        let (mut freed, mut new) = (Vec::new(), Vec::new());
+        for (idx, was_alive) in candidates.into_iter().zip(was_alive) {
+            if was_alive {
+                self.set_size(idx, 0);
+                self.count -= 1;
+            }
+            let alive = self.alive(idx);
+            if alive {
+                self.set_size(idx, measure(ctx, self.pool, idx));
+                self.count += 1;
+            }
+            match (was_alive, alive) {
+                (true, false) => freed.push(idx),
+                // taken from garbage or added at the end of the pool
+                (false, true) => new.push(idx),
+                _ => {}
+            }
+        }
+        self.sizes.truncate(len);
+        (freed, new)
+    }
+}
+
+/// size of value at idx in bytes
+fn measure(ctx: &Context, pool: Pool, idx: usize) -> usize {
+    let mem = &ctx.memory;
+    match pool {
+        Pool::Heap => mem.heap.data.get(idx).map_or(0, |obj| obj.len() * size_of::<Types>()),
+        Pool::String => mem.strings.pool.get(idx).map_or(0, |str| str.len() * size_of::<char>()),
+    }
+}
+
+struct Tracker {
+    res: Allocations,
+    heap: PoolState,
+    strings: PoolState,
+}
+
+impl Tracker {
+    /// records allocations made by instruction at site and deallocations
+    fn sync(&mut self, ctx: &Context, site: Option<usize>, swept: bool) {
+        let time = self.res.total;
+        let mut changed = false;
+        for state in [&mut self.heap, &mut self.strings] {
+            let (len, garbage) = state.pool_of(ctx);
+            if !swept
+                && state.len == len
+                && state.garbage.len() == garbage.len()
+                && state.garbage.last() == garbage.last()
+            {
+                continue;
+            }
+            changed = true;
+            let (freed, new) = state.update(ctx, swept);
+            for idx in freed {
+                if let Some(alloc) = state.live.remove(&idx) {
+                    self.res.allocations[alloc].freed = Some(time);
+                }
+            }
+            // a sweep is survived by everything that is still alive after it
+            if swept {
+                for alloc in state.live.values() {
+                    self.res.allocations[*alloc].sweeps += 1;
+                }
+            }
+            // values changed in place are measured again when reached through registers
+            for value in ctx.memory.registers.iter() {
+                let idx = match (state.pool, value) {
+                    (Pool::Heap, Types::Pointer(idx, PointerTypes::Object | PointerTypes::Heap(_)))
+                    | (Pool::String, Types::Pointer(idx, PointerTypes::String)) => *idx,
+                    _ => continue,
+                };
+                if state.alive(idx) {
+                    state.set_size(idx, measure(ctx, state.pool, idx));
+                }
+            }
+            if let Some(site) = site {
+                for idx in new {
+                    state.live.insert(idx, self.res.allocations.len());
+                    self.res.allocations.push(Allocation {
+                        pool: state.pool,
+                        idx,
+                        site,
+                        size: state.sizes[idx],
+                        born: time,
+                        freed: None,
+                        sweeps: 0,
+                    });
+                }
+            }
+        }
+        if changed {
+            let sample = self.sample(time);
+            if self.res.timeline.last().is_none_or(|last| !last.same_sizes(&sample)) {
+                self.res.timeline.push(sample);
+            }
+        }
+    }
+
+    fn sample(&self, time: usize) -> Sample {
+        Sample {
+            time,
+            objects: self.heap.count,
+            heap_bytes: self.heap.bytes,
+            strings: self.strings.count,
+            string_bytes: self.strings.bytes,
+        }
+    }
+}
+
+impl Sample {
+    fn same_sizes(&self, other: &Sample) -> bool {
+        (self.objects, self.heap_bytes, self.strings, self.string_bytes)
+            == (other.objects, other.heap_bytes, other.strings, other.string_bytes)
+    }
+}
+
+/// runs program to the end, tracking every allocation
+pub fn run(ctx: &mut Context) -> Allocations {
+    let mut tracker = Tracker {
+        res: Allocations::default(),
+        heap: PoolState::new(Pool::Heap, ctx),
+        strings: PoolState::new(Pool::String, ctx),
+    };
+    tracker.res.timeline.push(tracker.sample(0));
+    loop {
+        let code_ptr = ctx.code.ptr;
+        let instr = ctx.code.data[code_ptr];
+        let sweeps = ctx.memory.gc.sweeps_count;
+        let running = ctx.read_line();
+        tracker.res.total += 1;
+        if let Instructions::RAlc(_) = instr {
+            if let Types::Pointer(obj, _) = ctx.memory.registers[POINTER_REG] {
+                let size = ctx.memory.heap.data.get(obj).map_or(0, |obj| obj.len() * size_of::<Types>());
+                let entry = tracker.res.reallocations.entry(code_ptr).or_default();
+                entry.0 += 1;
+                entry.1 += size;
+                if let Some(alloc) = tracker.heap.live.get(&obj) {
+                    tracker.res.allocations[*alloc].size = size;
+                }
+                if tracker.heap.alive(obj) {
+                    tracker.heap.set_size(obj, size);
+                }
+            }
+        }
+        // Sweep instructions
+        let swept = ctx.memory.gc.sweeps_count != sweeps;
+        tracker.sync(ctx, Some(code_ptr), swept);
+        // same as Context::run
+        let sweeps = ctx.memory.gc.sweeps_count;
+        ctx.memory.gc_sweep_unoptimized();
+        let swept = ctx.memory.gc.sweeps_count != sweeps;
+        tracker.sync(ctx, None, swept);
+        if !running {
+            break;
+        }
+    }
+    tracker.res
+}
+
+impl Allocations {
+    /// text report of allocation sites and longest retained objects
+    pub fn report(&self, ctx: &Context) -> String {
+        let mut res = String::new();
+        writeln!(res, "Allocation profile").unwrap();
+        let bytes: usize = self.allocations.iter().map(|alloc| alloc.size).sum();
+        let live = self.allocations.iter().filter(|alloc| alloc.freed.is_none()).count();
+        writeln!(
+            res,
+            "{} allocations, {bytes} bytes, {live} alive at exit, {} sweeps",
+            self.allocations.len(),
+            ctx.memory.gc.sweeps_count
+        )
+        .unwrap();
+        // first sample with the largest size
+        let peak = |size: fn(&Sample) -> usize| {
+            self.timeline.iter().rev().max_by_key(|sample| size(sample))
+        };
+        if let (Some(peak_heap), Some(peak_strings)) =
+            (peak(|sample| sample.heap_bytes), peak(|sample| sample.string_bytes))
+        {
+            writeln!(
+                res,
+                "Peak heap: {} objects, {} bytes at instruction {}",
+                peak_heap.objects, peak_heap.heap_bytes, peak_heap.time
+            )
+            .unwrap();
+            writeln!(
+                res,
+                "Peak strings: {} strings, {} bytes at instruction {}",
+                peak_strings.strings, peak_strings.string_bytes, peak_strings.time
+            )
+            .unwrap();
+        }
+
+        // site, pool -> count, bytes, alive, sweeps survived
+        let mut sites: HashMap<(usize, Pool), (usize, usize, usize, usize)> = HashMap::new();
+        for alloc in self.allocations.iter() {
+            let site = sites.entry((alloc.site, alloc.pool)).or_default();
+            site.0 += 1;
+            site.1 += alloc.size;
+            site.2 += alloc.freed.is_none() as usize;
+            site.3 += alloc.sweeps;
+        }
+        let mut sites: Vec<_> = sites.into_iter().collect();
+        sites.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(b.1 .1.cmp(&a.1 .1)).then(a.0.cmp(&b.0)));
+        writeln!(res, "\nAllocation sites").unwrap();
+        writeln!(
+            res,
+            "{:>10} {:>10} {:>8} {:>12}  site",
+            "count", "bytes", "alive", "avg sweeps"
+        )
+        .unwrap();
+        for ((site, pool), (count, bytes, alive, sweeps)) in sites.iter().take(20) {
+            writeln!(
+                res,
+                "{count:>10} {bytes:>10} {alive:>8} {:>12.1}  {site}: {:?} in {} ({pool})",
+                *sweeps as f64 / *count as f64,
+                ctx.code.data[*site],
+                debugger::fun_name(ctx, *site)
+            )
+            .unwrap();
+        }
+        if sites.len() > 20 {
+            writeln!(res, "{:>10} more", sites.len() - 20).unwrap();
+        }
+        if !self.reallocations.is_empty() {
+            writeln!(res, "\nReallocations").unwrap();
+            writeln!(res, "{:>10} {:>10}  site", "count", "bytes").unwrap();
+            let mut reallocations: Vec<_> = self.reallocations.iter().collect();
+            reallocations.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+            for (site, (count, bytes)) in reallocations {
+                writeln!(
+                    res,
+                    "{count:>10} {bytes:>10}  {site}: {:?} in {}",
+                    ctx.code.data[*site],
+                    debugger::fun_name(ctx, *site)
+                )
+                .unwrap();
+            }
+        }
+
+        let mut retained: Vec<&Allocation> = self.allocations.iter().collect();
+        retained.sort_by_key(|alloc| {
+            let lifetime = alloc.freed.unwrap_or(self.total) - alloc.born;
+            (std::cmp::Reverse(lifetime), alloc.born)
+        });
+        writeln!(res, "\nLongest retained").unwrap();
+        writeln!(
+            res,
+            "{:>10} {:>8} {:>10}  object",
+            "lifetime", "sweeps", "bytes"
+        )
+        .unwrap();
+        for alloc in retained.iter().take(10) {
+            let lifetime = alloc.freed.unwrap_or(self.total) - alloc.born;
+            let state = match alloc.freed {
+                Some(_) => "",
+                None => ", alive",
+            };
+            writeln!(
+                res,
+                "{lifetime:>10} {:>8} {:>10}  {} {} from {}: {:?} in {}{state}",
+                alloc.sweeps,
+                alloc.size,
+                alloc.pool,
+                alloc.idx,
+                alloc.site,
+                ctx.code.data[alloc.site],
+                debugger::fun_name(ctx, alloc.site)
+            )
+            .unwrap();
+        }
+        res
+    }
+
+    /// heap and string pool sizes over executed instructions as CSV
+    pub fn timeline_csv(&self) -> String {
+        let mut res = String::from("instruction,objects,heap_bytes,strings,string_bytes\n");
+        for sample in self.timeline.iter() {
+            writeln!(
+                res,
+                "{},{},{},{},{}",
+                sample.time, sample.objects, sample.heap_bytes, sample.strings, sample.string_bytes
+            )
+            .unwrap();
+        }
+        res
+    }
+}
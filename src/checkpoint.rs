@@ -0,0 +1,441 @@
+//! Checkpoints of a running VM, written with --checkpoint <file> and resumed with --resume <file>
+//!
+//! Checkpoint starts with the program in the same format as .rdbin (code, stack, strings, types,
+//! functions and debug info), followed by everything that changes during execution.
+//! Libraries are stored with their paths and owners like in .rdbin, followed by the name and
+//! the state returned by Library::save_state, they have to be loaded again before their state is restored.
+
+use runtime::{
+    runtime_error::ErrTypes,
+    runtime_types::{
+        Catch, Context, Exception, ExitCodes, FinallyExit, PendingFinally, Registers, Types,
+        FREEZED_REG_SIZE, REGISTER_SIZE,
+    },
+};
+
+use crate::stringify::{
+    self, b256str, byte_into_string, bytes_into_value, push_chars, push_str, read_char,
+    read_number, read_str, str_into_byte, value_into_byte, ShLib,
+};
+
+pub const MAGIC_NUMBER: &str = "RUDACHK";
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// serializes context, fails if any library refuses to be checkpointed
+pub fn save(ctx: &Context, shared_libs: &[ShLib]) -> Result<String, String> {
+    let mut res = String::from(MAGIC_NUMBER);
+    res.push_str(&stringify::stringify(ctx, shared_libs));
+    res.push_str(&b256str(ctx.code.ptr, 8));
+    option_into_string(ctx.break_code, &mut res);
+    exit_code_into_string(&ctx.exit_code, &mut res);
+    values_into_string(&ctx.memory.registers, &mut res);
+
+    let stack = &ctx.memory.stack;
+    res.push_str(&b256str(stack.ptr, 8));
+    res.push_str(&b256str(stack.globals, 8));
+    res.push_str(&b256str(stack.call_stack.len(), 8));
+    for frame in stack.call_stack.iter() {
+        values_into_string(&frame.reg_freeze, &mut res);
+        res.push_str(&b256str(frame.end, 8));
+        res.push_str(&b256str(frame.code_ptr, 8));
+        res.push_str(&b256str(frame.pointers_len, 8));
+        option_into_string(frame.env, &mut res);
+        res.push(frame.call as u8 as char);
+    }
+
+    let heap = &ctx.memory.heap;
+    res.push_str(&b256str(heap.data.len(), 8));
+    for obj in heap.data.iter() {
+        res.push_str(&b256str(obj.len(), 8));
+        values_into_string(obj, &mut res);
+    }
+    usizes_into_string(&heap.garbage, &mut res);
+    usizes_into_string(&ctx.memory.strings.garbage, &mut res);
+
+    let gc = &ctx.memory.gc;
+    res.push_str(&b256str(gc.sweeps_count, 8));
+    res.push_str(&b256str(gc.memory_swept, 8));
+    res.push(gc.disabled as u8 as char);
+    res.push_str(&b256str(gc.sweep_threshold, 8));
+
+    let catches = &ctx.catches;
+    res.push_str(&b256str(catches.cache.len(), 8));
+    for catch in catches.cache.iter() {
+        res.push_str(&b256str(catch.code_ptr, 8));
+        res.push_str(&b256str(catch.cs_ptr, 8));
+        option_into_string(catch.id, &mut res);
+        values_into_string(&catch.reg_freeze, &mut res);
+        res.push(catch.finally as u8 as char);
+    }
+    res.push_str(&b256str(catches.pending.len(), 8));
+    for pending in catches.pending.iter() {
+        res.push_str(&b256str(pending.depth, 8));
+        res.push_str(&b256str(pending.cs_ptr, 8));
+        match &pending.exit {
+            FinallyExit::Resume(code_ptr, registers) => {
+                res.push(0 as char);
+                res.push_str(&b256str(*code_ptr, 8));
+                values_into_string(registers, &mut res);
+            }
+            FinallyExit::Propagate(exception) => {
+                res.push(1 as char);
+                exception_into_string(exception, &mut res);
+            }
+        }
+    }
+
+    match &ctx.exception {
+        Some(exception) => {
+            res.push(1 as char);
+            exception_into_string(exception, &mut res);
+        }
+        None => res.push(0 as char),
+    }
+
+    res.push_str(&b256str(ctx.memory.runtime_args.len(), 8));
+    for arg in ctx.memory.runtime_args.iter() {
+        push_str(arg, &mut res);
+    }
+
+    res.push_str(&b256str(ctx.libs.len(), 8));
+    for lib in ctx.libs.iter() {
+        let state = lib
+            .save_state()
+            .map_err(|err| format!("Library '{}' refused checkpoint: {err}", lib.name()))?;
+        push_str(&lib.name(), &mut res);
+        push_chars(&state.iter().map(|byte| *byte as char).collect(), &mut res);
+    }
+    Ok(res)
+}
+
+/// writes checkpoint to path, previous checkpoint is replaced only after the new one is written
+pub fn write(ctx: &Context, shared_libs: &[ShLib], path: &str) -> Result<(), String> {
+    let checkpoint = save(ctx, shared_libs)?;
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, checkpoint).map_err(|err| err.to_string())?;
+    std::fs::rename(&tmp, path).map_err(|err| err.to_string())
+}
+
+/// state of a library stored in checkpoint
+pub struct LibState {
+    pub name: String,
+    pub state: Vec<u8>,
+}
+
+/// restores context from checkpoint, libraries are returned and have to be loaded
+/// and then restored with restore_libs
+pub fn restore(ctx: &mut Context, str: &str) -> Result<(Vec<ShLib>, Vec<LibState>), String> {
+    let str = str
+        .strip_prefix(MAGIC_NUMBER)
+        .ok_or("The file you are trying to load is not a valid Ruda checkpoint")?;
+    let chars = &mut str.chars().peekable();
+    let data = stringify::parse_chars(chars)?;
+    ctx.code.data = data.instructions;
+    ctx.code.debug = data.debug;
+    ctx.memory.stack.data = data.values;
+    ctx.memory.strings.pool = data.strings;
+    ctx.memory.non_primitives = data.non_primitives;
+    ctx.memory.fun_table = data.fun_table;
+
+    ctx.code.ptr = read_number(chars, 8)?;
+    ctx.break_code = read_option(chars)?;
+    ctx.exit_code = read_exit_code(chars)?;
+    ctx.memory.registers = read_registers(chars)?;
+
+    let stack = &mut ctx.memory.stack;
+    stack.ptr = read_number(chars, 8)?;
+    stack.globals = read_number(chars, 8)?;
+    let len = read_number(chars, 8)?;
+    stack.call_stack.clear();
+    for _ in 0..len {
+        let mut frame = runtime::runtime_types::CallStack::new();
+        frame.reg_freeze = read_freeze(chars)?;
+        frame.end = read_number(chars, 8)?;
+        frame.code_ptr = read_number(chars, 8)?;
+        frame.pointers_len = read_number(chars, 8)?;
+        frame.env = read_option(chars)?;
+        frame.call = read_number(chars, 1)? == 1;
+        stack.call_stack.push(frame);
+    }
+    if stack.ptr >= stack.call_stack.len() {
+        return Err(format!(
+            "Checkpoint points to frame {}, but has only {} frames",
+            stack.ptr,
+            stack.call_stack.len()
+        ));
+    }
+    if ctx.code.ptr >= ctx.code.data.len() {
+        return Err(format!(
+            "Checkpoint points to instruction {}, but has only {} instructions",
+            ctx.code.ptr,
+            ctx.code.data.len()
+        ));
+    }
+
+    let len = read_number(chars, 8)?;
+    let mut heap = Vec::new();
+    for _ in 0..len {
+        let len = read_number(chars, 8)?;
+        heap.push(read_values(chars, len)?);
+    }
+    ctx.memory.heap.data = heap;
+    ctx.memory.heap.garbage = read_usizes(chars)?;
+    ctx.memory.strings.garbage = read_usizes(chars)?;
+
+    let gc = &mut ctx.memory.gc;
+    gc.sweeps_count = read_number(chars, 8)?;
+    gc.memory_swept = read_number(chars, 8)?;
+    gc.disabled = read_number(chars, 1)? != 0;
+    gc.sweep_threshold = read_number(chars, 8)?;
+
+    let len = read_number(chars, 8)?;
+    ctx.catches.cache.clear();
+    for _ in 0..len {
+        ctx.catches.cache.push(Catch {
+            code_ptr: read_number(chars, 8)?,
+            cs_ptr: read_number(chars, 8)?,
+            id: read_option(chars)?,
+            reg_freeze: read_freeze(chars)?,
+            finally: read_number(chars, 1)? != 0,
+        });
+    }
+    let len = read_number(chars, 8)?;
+    ctx.catches.pending.clear();
+    for _ in 0..len {
+        let depth = read_number(chars, 8)?;
+        let cs_ptr = read_number(chars, 8)?;
+        let exit = match read_number(chars, 1)? {
+            0 => FinallyExit::Resume(read_number(chars, 8)?, read_registers(chars)?),
+            _ => FinallyExit::Propagate(read_exception(chars)?),
+        };
+        ctx.catches.pending.push(PendingFinally {
+            depth,
+            cs_ptr,
+            exit,
+        });
+    }
+
+    ctx.exception = match read_number(chars, 1)? {
+        0 => None,
+        _ => Some(read_exception(chars)?),
+    };
+
+    let len = read_number(chars, 8)?;
+    ctx.memory.runtime_args = (0..len)
+        .map(|_| read_str(chars))
+        .collect::<Result<_, _>>()?;
+
+    let len = read_number(chars, 8)?;
+    let mut libs = Vec::new();
+    for _ in 0..len {
+        let name = read_str(chars)?;
+        let len = read_number(chars, 8)?;
+        let state = (0..len)
+            .map(|_| read_char(chars).map(|char| char as u8))
+            .collect::<Result<_, _>>()?;
+        libs.push(LibState { name, state });
+    }
+    Ok((data.shared_libs, libs))
+}
+
+/// restores state of loaded libraries, they must be the same libraries in the same order as in checkpoint
+pub fn restore_libs(ctx: &mut Context, libs: &[LibState]) -> Result<(), String> {
+    if ctx.libs.len() != libs.len() {
+        return Err(format!(
+            "Checkpoint has {} libraries, but {} were loaded",
+            libs.len(),
+            ctx.libs.len()
+        ));
+    }
+    for (lib, saved) in ctx.libs.iter_mut().zip(libs.iter()) {
+        if lib.name() != saved.name {
+            return Err(format!(
+                "Expected library '{}', found '{}'",
+                saved.name,
+                lib.name()
+            ));
+        }
+        lib.restore_state(&saved.state)
+            .map_err(|err| format!("Library '{}' failed to restore: {err}", saved.name))?;
+    }
+    Ok(())
+}
+
+fn option_into_string(option: Option<usize>, str: &mut String) {
+    match option {
+        Some(n) => {
+            str.push(1 as char);
+            str.push_str(&b256str(n, 8));
+        }
+        None => str.push(0 as char),
+    }
+}
+
+fn read_option(chars: &mut Chars) -> Result<Option<usize>, String> {
+    match read_number(chars, 1)? {
+        0 => Ok(None),
+        _ => Ok(Some(read_number(chars, 8)?)),
+    }
+}
+
+fn exception_into_string(exception: &Exception, str: &mut String) {
+    value_into_byte(exception.value, str);
+    usizes_into_string(&exception.trace, str);
+    match &exception.error {
+        Some(err) => {
+            str.push(1 as char);
+            err_into_string(err, str);
+        }
+        None => str.push(0 as char),
+    }
+}
+
+fn read_exception(chars: &mut Chars) -> Result<Exception, String> {
+    Ok(Exception {
+        value: bytes_into_value(chars)?,
+        trace: read_usizes(chars)?,
+        error: match read_number(chars, 1)? {
+            0 => None,
+            _ => Some(read_err(chars)?),
+        },
+    })
+}
+
+fn values_into_string(values: &[Types], str: &mut String) {
+    for value in values.iter() {
+        value_into_byte(*value, str);
+    }
+}
+
+fn read_values(chars: &mut Chars, len: usize) -> Result<Vec<Types>, String> {
+    (0..len).map(|_| bytes_into_value(chars)).collect()
+}
+
+fn read_registers(chars: &mut Chars) -> Result<Registers, String> {
+    let mut registers = [Types::Null; REGISTER_SIZE];
+    for reg in registers.iter_mut() {
+        *reg = bytes_into_value(chars)?;
+    }
+    Ok(registers)
+}
+
+fn read_freeze(chars: &mut Chars) -> Result<[Types; FREEZED_REG_SIZE], String> {
+    let mut registers = [Types::Null; FREEZED_REG_SIZE];
+    for reg in registers.iter_mut() {
+        *reg = bytes_into_value(chars)?;
+    }
+    Ok(registers)
+}
+
+pub fn usizes_into_string(values: &[usize], str: &mut String) {
+    str.push_str(&b256str(values.len(), 8));
+    for value in values.iter() {
+        str.push_str(&b256str(*value, 8));
+    }
+}
+
+pub fn read_usizes(chars: &mut Chars) -> Result<Vec<usize>, String> {
+    let len = read_number(chars, 8)?;
+    (0..len).map(|_| read_number(chars, 8)).collect()
+}
+
+pub fn exit_code_into_string(exit_code: &ExitCodes, str: &mut String) {
+    match exit_code {
+        ExitCodes::End => str.push(0 as char),
+        ExitCodes::Break(code) => {
+            str.push(1 as char);
+            str.push_str(&b256str(*code, 8));
+        }
+        ExitCodes::Exception => str.push(2 as char),
+        ExitCodes::Internal(err) => {
+            str.push(3 as char);
+            err_into_string(err, str);
+        }
+    }
+}
+
+pub fn read_exit_code(chars: &mut Chars) -> Result<ExitCodes, String> {
+    Ok(match read_number(chars, 1)? {
+        0 => ExitCodes::End,
+        1 => ExitCodes::Break(read_number(chars, 8)?),
+        2 => ExitCodes::Exception,
+        3 => ExitCodes::Internal(read_err(chars)?),
+        _ => return Err("Invalid exit code".to_string()),
+    })
+}
+
+pub fn err_into_string(err: &ErrTypes, str: &mut String) {
+    match err {
+        ErrTypes::CrossTypeOperation(a, b, instr) => {
+            str.push(0 as char);
+            value_into_byte(*a, str);
+            value_into_byte(*b, str);
+            byte_into_string(*instr, str);
+        }
+        ErrTypes::WrongTypeOperation(a, instr) => {
+            str.push(1 as char);
+            value_into_byte(*a, str);
+            byte_into_string(*instr, str);
+        }
+        ErrTypes::InvalidType(a, b) => {
+            str.push(2 as char);
+            value_into_byte(*a, str);
+            value_into_byte(*b, str);
+        }
+        ErrTypes::Expected(a, b) => {
+            str.push(3 as char);
+            value_into_byte(*a, str);
+            value_into_byte(*b, str);
+        }
+        ErrTypes::ImplicitCast(a, b) => {
+            str.push(4 as char);
+            value_into_byte(*a, str);
+            value_into_byte(*b, str);
+        }
+        ErrTypes::StackOverflow => str.push(5 as char),
+        ErrTypes::CatchOwerflow => str.push(6 as char),
+        ErrTypes::MethodNotFound => str.push(7 as char),
+        ErrTypes::Message(msg) => {
+            str.push(8 as char);
+            push_str(msg, str);
+        }
+        ErrTypes::ParseError(text, value) => {
+            str.push(9 as char);
+            push_str(text, str);
+            value_into_byte(*value, str);
+        }
+        ErrTypes::InvalidChar(value) => {
+            str.push(10 as char);
+            value_into_byte(*value, str);
+        }
+        ErrTypes::StackUnderflow => str.push(11 as char),
+        ErrTypes::InvalidFunction(id) => {
+            str.push(12 as char);
+            str.push_str(&b256str(*id, 8));
+        }
+    }
+}
+
+pub fn read_err(chars: &mut Chars) -> Result<ErrTypes, String> {
+    Ok(match read_number(chars, 1)? {
+        0 => ErrTypes::CrossTypeOperation(
+            bytes_into_value(chars)?,
+            bytes_into_value(chars)?,
+            str_into_byte(chars)?,
+        ),
+        1 => ErrTypes::WrongTypeOperation(bytes_into_value(chars)?, str_into_byte(chars)?),
+        2 => ErrTypes::InvalidType(bytes_into_value(chars)?, bytes_into_value(chars)?),
+        3 => ErrTypes::Expected(bytes_into_value(chars)?, bytes_into_value(chars)?),
+        4 => ErrTypes::ImplicitCast(bytes_into_value(chars)?, bytes_into_value(chars)?),
+        5 => ErrTypes::StackOverflow,
+        6 => ErrTypes::CatchOwerflow,
+        7 => ErrTypes::MethodNotFound,
+        8 => ErrTypes::Message(read_str(chars)?),
+        9 => ErrTypes::ParseError(read_str(chars)?, bytes_into_value(chars)?),
+        10 => ErrTypes::InvalidChar(bytes_into_value(chars)?),
+        11 => ErrTypes::StackUnderflow,
+        12 => ErrTypes::InvalidFunction(read_number(chars, 8)?),
+        _ => return Err("Invalid error type".to_string()),
+    })
+}
@@ -0,0 +1,128 @@
+//! Bytecode coverage, enabled with --coverage <file>
+//!
+//! Report is written in lcov format. Lines come from debug info of the binary, without it
+//! the program itself is the source file and line N is instruction N - 1 (same as in the debug adapter).
+//! Branch 0 of each Brnc is the jump taken when reg(GENERAL_REG1) is true, branch 1 when it is false.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use runtime::runtime_types::{Context, Instructions, Types, GENERAL_REG1};
+
+use crate::debugger;
+
+#[derive(Default)]
+pub struct Coverage {
+    /// hit count per instruction index
+    pub hits: Vec<usize>,
+    /// (true, false) count per index of Brnc
+    pub branches: BTreeMap<usize, (usize, usize)>,
+}
+
+/// runs program to the end, counting executed instructions and branch directions
+pub fn run(ctx: &mut Context) -> Coverage {
+    let mut coverage = Coverage {
+        hits: vec![0; ctx.code.data.len()],
+        branches: BTreeMap::new(),
+    };
+    for (ptr, instr) in ctx.code.data.iter().enumerate() {
+        if let Instructions::Brnc(_, _) = instr {
+            coverage.branches.insert(ptr, (0, 0));
+        }
+    }
+    loop {
+        let code_ptr = ctx.code.ptr;
+        coverage.hits[code_ptr] += 1;
+        if let Instructions::Brnc(_, _) = ctx.code.data[code_ptr] {
+            let branch = coverage.branches.entry(code_ptr).or_default();
+            match ctx.memory.registers[GENERAL_REG1] {
+                Types::Bool(true) => branch.0 += 1,
+                Types::Bool(false) => branch.1 += 1,
+                _ => {}
+            }
+        }
+        if !debugger::step(ctx) {
+            break;
+        }
+    }
+    coverage
+}
+
+/// lines of one source file
+#[derive(Default)]
+struct SourceFile {
+    /// line -> hit count of its most executed instruction
+    lines: BTreeMap<usize, usize>,
+    /// line, name, hit count
+    funs: Vec<(usize, String, usize)>,
+    /// line, index of Brnc, (true, false), whether Brnc was executed
+    branches: Vec<(usize, usize, (usize, usize), bool)>,
+}
+
+impl Coverage {
+    /// lcov report, program is the source file used when there is no debug info
+    pub fn lcov(&self, ctx: &Context, program: &str) -> String {
+        let mut files: BTreeMap<String, SourceFile> = BTreeMap::new();
+        let location = |ptr: usize| -> Option<(String, usize)> {
+            match &ctx.code.debug {
+                Some(debug) => {
+                    let entry = debug.location(ptr)?;
+                    Some((debug.files.get(entry.file)?.clone(), entry.line))
+                }
+                None => Some((program.to_string(), ptr + 1)),
+            }
+        };
+        for (ptr, hits) in self.hits.iter().enumerate() {
+            if let Some((file, line)) = location(ptr) {
+                let count = files.entry(file).or_default().lines.entry(line).or_default();
+                *count = (*count).max(*hits);
+            }
+        }
+        for fun in ctx.memory.fun_table.iter() {
+            if let Some((file, line)) = location(fun.loc) {
+                let hits = self.hits.get(fun.loc).copied().unwrap_or(0);
+                files.entry(file).or_default().funs.push((line, fun.name.clone(), hits));
+            }
+        }
+        for (ptr, taken) in self.branches.iter() {
+            if let Some((file, line)) = location(*ptr) {
+                let executed = self.hits[*ptr] > 0;
+                files.entry(file).or_default().branches.push((line, *ptr, *taken, executed));
+            }
+        }
+        let mut res = String::new();
+        for (path, file) in files.iter() {
+            writeln!(res, "TN:").unwrap();
+            writeln!(res, "SF:{path}").unwrap();
+            for (line, name, _) in file.funs.iter() {
+                writeln!(res, "FN:{line},{name}").unwrap();
+            }
+            for (_, name, hits) in file.funs.iter() {
+                writeln!(res, "FNDA:{hits},{name}").unwrap();
+            }
+            writeln!(res, "FNF:{}", file.funs.len()).unwrap();
+            writeln!(res, "FNH:{}", file.funs.iter().filter(|fun| fun.2 > 0).count()).unwrap();
+            let mut branches_hit = 0;
+            for (line, ptr, (on_true, on_false), executed) in file.branches.iter() {
+                for (branch, taken) in [on_true, on_false].iter().enumerate() {
+                    if !executed {
+                        writeln!(res, "BRDA:{line},{ptr},{branch},-").unwrap();
+                        continue;
+                    }
+                    if **taken > 0 {
+                        branches_hit += 1;
+                    }
+                    writeln!(res, "BRDA:{line},{ptr},{branch},{taken}").unwrap();
+                }
+            }
+            writeln!(res, "BRF:{}", file.branches.len() * 2).unwrap();
+            writeln!(res, "BRH:{branches_hit}").unwrap();
+            for (line, hits) in file.lines.iter() {
+                writeln!(res, "DA:{line},{hits}").unwrap();
+            }
+            writeln!(res, "LF:{}", file.lines.len()).unwrap();
+            writeln!(res, "LH:{}", file.lines.values().filter(|hits| **hits > 0).count()).unwrap();
+            writeln!(res, "end_of_record").unwrap();
+        }
+        res
+    }
+}
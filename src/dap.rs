@@ -0,0 +1,735 @@
+//! Debug Adapter Protocol server over stdio, enabled with --dap
+//!
+//! Launch arguments: "program" (path to .rdbin) or "test" (id of test scenario),
+//! "stopOnEntry" and "args" (runtime arguments).
+//! Sources are mapped using debug info of the binary. Without it, the program itself
+//! is the source and line N is instruction N - 1.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use runtime::runtime_types::{Context, ExitCodes, PointerTypes, Types, REGISTER_SIZE};
+
+use crate::{
+    debugger::{self, Stop},
+    json::Json,
+    timetravel::History,
+};
+
+const THREAD_ID: usize = 1;
+
+/// sends messages to client, shared with thread forwarding program output
+struct Client {
+    out: Mutex<Box<dyn Write + Send>>,
+    seq: AtomicUsize,
+}
+
+impl Client {
+    fn send(&self, kind: &str, mut body: Vec<(&str, Json)>) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        body.insert(0, ("seq", seq.into()));
+        body.insert(1, ("type", kind.into()));
+        let msg = Json::object(body).to_string();
+        let mut out = self.out.lock().unwrap();
+        write!(out, "Content-Length: {}\r\n\r\n{msg}", msg.len()).unwrap();
+        out.flush().unwrap();
+    }
+    fn event(&self, event: &str, body: Json) {
+        self.send("event", vec![("event", event.into()), ("body", body)]);
+    }
+}
+
+/// what a variablesReference points to
+enum VarRef {
+    Registers,
+    /// index of frame in call stack, function of frame
+    Frame(usize, Option<usize>),
+    Heap,
+    Object(usize),
+}
+
+pub struct Server {
+    client: Arc<Client>,
+    ctx: Option<Context>,
+    history: History,
+    max_call_depth: usize,
+    max_catch_depth: usize,
+    /// path of launched program, used as source when there is no debug info
+    program: String,
+    stop_on_entry: bool,
+    source_breakpoints: HashMap<String, HashSet<usize>>,
+    fun_breakpoints: HashSet<usize>,
+    /// variablesReference - 1, valid until execution continues
+    refs: Vec<VarRef>,
+    ended: bool,
+}
+
+impl Server {
+    /// history is the most instructions that can be undone with stepBack and reverseContinue
+    pub fn new(max_call_depth: usize, max_catch_depth: usize, history: usize) -> Self {
+        Self {
+            client: Arc::new(Client {
+                out: Mutex::new(Box::new(io::stdout())),
+                seq: AtomicUsize::new(0),
+            }),
+            ctx: None,
+            history: History::new(history),
+            max_call_depth,
+            max_catch_depth,
+            program: String::new(),
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            fun_breakpoints: HashSet::new(),
+            refs: Vec::new(),
+            ended: false,
+        }
+    }
+
+    pub fn serve(mut self) {
+        self.capture_output();
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        while let Some(msg) = read_message(&mut input) {
+            let request = match Json::parse(&msg) {
+                Ok(request) => request,
+                Err(err) => {
+                    eprintln!("Invalid message: {err}");
+                    continue;
+                }
+            };
+            let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+            let args = request.get("arguments").cloned().unwrap_or(Json::Null);
+            let request_seq = request.get("seq").and_then(Json::as_usize).unwrap_or(0);
+            let result = self.handle(command, &args);
+            let mut response = vec![
+                ("request_seq", request_seq.into()),
+                ("command", command.into()),
+            ];
+            match &result {
+                Ok(_) => response.push(("success", true.into())),
+                Err(message) => {
+                    response.push(("success", false.into()));
+                    response.push(("message", message.as_str().into()));
+                }
+            }
+            if let Ok(Handled { body, .. }) = &result {
+                response.push(("body", body.clone()));
+            }
+            self.client.send("response", response);
+            match result {
+                Ok(Handled { then: Then::Nothing, .. }) | Err(_) => {}
+                Ok(Handled { then: Then::Initialized, .. }) => {
+                    self.client.event("initialized", Json::object(vec![]))
+                }
+                Ok(Handled { then: Then::Stopped(stop), .. }) => self.stopped(stop),
+                Ok(Handled { then: Then::Exit, .. }) => return,
+            }
+        }
+    }
+
+    fn handle(&mut self, command: &str, args: &Json) -> Result<Handled, String> {
+        match command {
+            "initialize" => Ok(Handled::body(Json::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsFunctionBreakpoints", true.into()),
+                ("supportsSetVariable", true.into()),
+                ("supportsStepBack", true.into()),
+            ]))),
+            "launch" => {
+                let mut ctx = match (args.get("program").and_then(Json::as_str), args.get("test")) {
+                    (Some(program), _) => {
+                        if !std::path::Path::new(program).exists() {
+                            return Err(format!("File '{program}' does not exist"));
+                        }
+                        self.program = program.to_string();
+                        crate::load(program, self.max_call_depth, self.max_catch_depth)
+                            .map_err(|err| format!("Failed to load '{program}': {err}"))?
+                            .0
+                    }
+                    (None, Some(test)) => {
+                        let mut ctx = Context::with_limits(self.max_call_depth, self.max_catch_depth);
+                        crate::test::test::test_init(test.as_usize(), &mut ctx);
+                        ctx.memory.stack.globals = ctx.memory.stack.data.len();
+                        self.program = "test.rdbin".to_string();
+                        ctx
+                    }
+                    (None, None) => return Err("Missing 'program' argument".to_string()),
+                };
+                if let Some(runtime_args) = args.get("args").and_then(Json::as_array) {
+                    ctx.memory.runtime_args = runtime_args
+                        .iter()
+                        .filter_map(|arg| arg.as_str().map(str::to_string))
+                        .collect();
+                }
+                self.stop_on_entry = args.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+                self.ctx = Some(ctx);
+                Ok(Handled::then(Then::Initialized))
+            }
+            "setBreakpoints" => {
+                let path = args
+                    .get("source")
+                    .and_then(|source| source.get("path"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                let lines: Vec<usize> = match args.get("breakpoints").and_then(Json::as_array) {
+                    Some(breakpoints) => breakpoints
+                        .iter()
+                        .filter_map(|bp| bp.get("line").and_then(Json::as_usize))
+                        .collect(),
+                    None => vec![],
+                };
+                let mut locs = HashSet::new();
+                let mut res = Vec::with_capacity(lines.len());
+                for line in lines {
+                    let loc = self.line_to_ptr(&path, line);
+                    if let Some(loc) = loc {
+                        locs.insert(loc);
+                    }
+                    res.push(Json::object(vec![
+                        ("verified", loc.is_some().into()),
+                        ("line", line.into()),
+                    ]));
+                }
+                self.source_breakpoints.insert(path, locs);
+                Ok(Handled::body(Json::object(vec![("breakpoints", res.into())])))
+            }
+            "setFunctionBreakpoints" => {
+                let ctx = self.ctx()?;
+                let mut locs = HashSet::new();
+                let mut res = vec![];
+                for bp in args.get("breakpoints").and_then(Json::as_array).unwrap_or(&vec![]) {
+                    let name = bp.get("name").and_then(Json::as_str).unwrap_or("");
+                    let found = debugger::fun_locs(ctx, name);
+                    res.push(Json::object(vec![("verified", (!found.is_empty()).into())]));
+                    locs.extend(found);
+                }
+                self.fun_breakpoints = locs;
+                Ok(Handled::body(Json::object(vec![("breakpoints", res.into())])))
+            }
+            "configurationDone" => {
+                let breakpoints = self.breakpoints();
+                let ctx = self.ctx()?;
+                if self.stop_on_entry {
+                    return Ok(Handled::then(Then::Stopped(None)));
+                }
+                let stop = match breakpoints.contains(&ctx.code.ptr) {
+                    true => Stop::Breakpoint,
+                    false => self.resume(|ctx, history, breakpoints| {
+                        debugger::run_until(ctx, history, breakpoints, |_| false)
+                    })?,
+                };
+                Ok(Handled::then(Then::Stopped(Some(stop))))
+            }
+            "threads" => Ok(Handled::body(Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![
+                    ("id", THREAD_ID.into()),
+                    ("name", "main".into()),
+                ])]
+                .into(),
+            )]))),
+            "continue" => {
+                let stop = self.resume(|ctx, history, breakpoints| {
+                    debugger::run_until(ctx, history, breakpoints, |_| false)
+                })?;
+                Ok(Handled {
+                    body: Json::object(vec![("allThreadsContinued", true.into())]),
+                    then: Then::Stopped(Some(stop)),
+                })
+            }
+            "next" => {
+                let stop = self.resume_line(debugger::step_over)?;
+                Ok(Handled::then(Then::Stopped(Some(stop))))
+            }
+            "stepIn" => {
+                let stop = self.resume_line(|ctx, history, _| debugger::single_step(ctx, history))?;
+                Ok(Handled::then(Then::Stopped(Some(stop))))
+            }
+            "stepOut" => {
+                let stop = self.resume(debugger::step_out)?;
+                Ok(Handled::then(Then::Stopped(Some(stop))))
+            }
+            "stepBack" => {
+                self.step_back_line()?;
+                Ok(Handled::then(Then::Stopped(Some(Stop::Step))))
+            }
+            "reverseContinue" => {
+                let breakpoints = self.breakpoints();
+                let found = self.go_back(|history, ctx| {
+                    history.run_back(ctx, |ptr| breakpoints.contains(&ptr))
+                })?;
+                let stop = match found {
+                    true => Stop::Breakpoint,
+                    false => Stop::Step,
+                };
+                Ok(Handled::then(Then::Stopped(Some(stop))))
+            }
+            // execution is synchronous, requests are only read while the program is stopped
+            "pause" => Err("Pause is not supported, program runs until it stops by itself".to_string()),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => {
+                let frame = args.get("frameId").and_then(Json::as_usize).unwrap_or(0);
+                let ctx = self.ctx()?;
+                let trace = ctx.stack_trace();
+                let code_ptr = *trace.get(frame).ok_or("Invalid frame")?;
+                // innermost frame of the function, the one below the call it made
+                let cs_ptr = match frame {
+                    0 => ctx.memory.stack.ptr,
+                    frame => ctx.call_frames()[frame - 1] - 1,
+                };
+                let fun = ctx.memory.fun_at(code_ptr);
+                let scopes = vec![
+                    ("Locals", VarRef::Frame(cs_ptr, fun), false),
+                    ("Registers", VarRef::Registers, false),
+                    ("Heap", VarRef::Heap, true),
+                ];
+                let mut res = vec![];
+                for (name, var_ref, expensive) in scopes {
+                    res.push(Json::object(vec![
+                        ("name", name.into()),
+                        ("variablesReference", self.add_ref(var_ref).into()),
+                        ("expensive", expensive.into()),
+                    ]));
+                }
+                Ok(Handled::body(Json::object(vec![("scopes", res.into())])))
+            }
+            "variables" => {
+                let reference = args.get("variablesReference").and_then(Json::as_usize).unwrap_or(0);
+                let variables = self.variables(reference)?;
+                Ok(Handled::body(Json::object(vec![("variables", variables.into())])))
+            }
+            "setVariable" => {
+                let reference = args.get("variablesReference").and_then(Json::as_usize).unwrap_or(0);
+                let name = args.get("name").and_then(Json::as_str).unwrap_or("");
+                let value = args
+                    .get("value")
+                    .and_then(Json::as_str)
+                    .and_then(debugger::parse_value)
+                    .ok_or("Invalid value, use 5, 5u, 1.5, 'c', true or null")?;
+                let reg = match self.refs.get(reference.wrapping_sub(1)) {
+                    Some(VarRef::Registers) => name
+                        .strip_prefix("reg(")
+                        .and_then(|name| name.split(')').next())
+                        .and_then(|reg| reg.parse::<usize>().ok())
+                        .filter(|reg| *reg < REGISTER_SIZE)
+                        .ok_or("Invalid register")?,
+                    _ => return Err("Only registers can be modified".to_string()),
+                };
+                let ctx = self.ctx.as_mut().ok_or("Program was not launched")?;
+                ctx.memory.registers[reg] = value;
+                self.history.modified(ctx);
+                Ok(Handled::body(Json::object(vec![
+                    ("value", format!("{value:+}").into()),
+                    ("type", format!("{value:#}").into()),
+                ])))
+            }
+            "disconnect" | "terminate" => Ok(Handled::then(Then::Exit)),
+            _ => Err(format!("Unsupported request '{command}'")),
+        }
+    }
+
+    fn ctx(&self) -> Result<&Context, String> {
+        self.ctx.as_ref().ok_or_else(|| "Program was not launched".to_string())
+    }
+
+    fn breakpoints(&self) -> HashSet<usize> {
+        let mut breakpoints = self.fun_breakpoints.clone();
+        for locs in self.source_breakpoints.values() {
+            breakpoints.extend(locs);
+        }
+        breakpoints
+    }
+
+    fn resume(
+        &mut self,
+        op: impl Fn(&mut Context, &mut History, &HashSet<usize>) -> Stop,
+    ) -> Result<Stop, String> {
+        if self.ended {
+            return Ok(Stop::Ended);
+        }
+        self.refs.clear();
+        let breakpoints = self.breakpoints();
+        let ctx = self.ctx.as_mut().ok_or("Program was not launched")?;
+        Ok(op(ctx, &mut self.history, &breakpoints))
+    }
+
+    /// repeats op until source line changes, single op if there is no debug info
+    fn resume_line(
+        &mut self,
+        op: impl Fn(&mut Context, &mut History, &HashSet<usize>) -> Stop,
+    ) -> Result<Stop, String> {
+        let start = self.source_line(self.ctx()?.code.ptr);
+        let depth = self.ctx()?.memory.stack.ptr;
+        loop {
+            let stop = self.resume(&op)?;
+            let ctx = self.ctx()?;
+            if stop != Stop::Step
+                || start.is_none()
+                || ctx.memory.stack.ptr != depth
+                || self.source_line(ctx.code.ptr) != start
+            {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// moves back in history, ended program can be resumed again
+    fn go_back(&mut self, op: impl FnOnce(&mut History, &mut Context) -> bool) -> Result<bool, String> {
+        self.refs.clear();
+        let ctx = self.ctx.as_mut().ok_or("Program was not launched")?;
+        let moved = op(&mut self.history, ctx);
+        if moved {
+            self.ended = false;
+        }
+        Ok(moved)
+    }
+
+    /// goes back to the first instruction of previous source line, one instruction if there is no debug info
+    fn step_back_line(&mut self) -> Result<(), String> {
+        let start = self.source_line(self.ctx()?.code.ptr);
+        loop {
+            if !self.go_back(History::step_back)? {
+                return Ok(());
+            }
+            let line = self.source_line(self.ctx()?.code.ptr);
+            if start.is_none() || line != start {
+                break;
+            }
+        }
+        // previous line may have more instructions before the current one
+        let line = self.source_line(self.ctx()?.code.ptr);
+        while line.is_some() && self.history.previous().is_some_and(|ptr| self.source_line(ptr) == line) {
+            self.go_back(History::step_back)?;
+        }
+        Ok(())
+    }
+
+    /// file and line of instruction from debug info
+    fn source_line(&self, code_ptr: usize) -> Option<(usize, usize)> {
+        let entry = self.ctx.as_ref()?.code.debug.as_ref()?.location(code_ptr)?;
+        Some((entry.file, entry.line))
+    }
+
+    fn line_to_ptr(&self, path: &str, line: usize) -> Option<usize> {
+        let ctx = self.ctx.as_ref()?;
+        match &ctx.code.debug {
+            Some(debug) => {
+                let file = debug
+                    .files
+                    .iter()
+                    .position(|file| path == file || path.ends_with(&format!("/{file}")))?;
+                debug
+                    .lines
+                    .iter()
+                    .find(|entry| entry.file == file && entry.line == line)
+                    .map(|entry| entry.ptr)
+            }
+            None => line.checked_sub(1).filter(|ptr| *ptr < ctx.code.data.len()),
+        }
+    }
+
+    /// sends stopped event, or exited and terminated events if program ended
+    fn stopped(&mut self, stop: Option<Stop>) {
+        io::stdout().flush().unwrap();
+        let (reason, text) = match stop {
+            None => ("entry", None),
+            Some(Stop::Step) => ("step", None),
+            Some(Stop::Breakpoint) => ("breakpoint", None),
+            Some(Stop::Watchpoint) => {
+                let ctx = match &mut self.ctx {
+                    Some(ctx) => ctx,
+                    None => return,
+                };
+                let mut text = vec![];
+                for hit in ctx.watch_hits.drain(..) {
+                    text.push(format!("{} changed: {hit}", ctx.watchpoints[hit.watchpoint].loc));
+                }
+                ("data breakpoint", Some(text.join("\n")))
+            }
+            Some(Stop::Ended) => {
+                let ctx = match &self.ctx {
+                    Some(ctx) => ctx,
+                    None => return,
+                };
+                match (&ctx.exit_code, self.ended) {
+                    // stop at uncaught exception so it can be inspected, program ends on next resume
+                    (ExitCodes::Exception | ExitCodes::Internal(_), false) => {
+                        self.ended = true;
+                        let text = match &ctx.exit_code {
+                            ExitCodes::Internal(err) => err.message(),
+                            _ => format!("Uncaught exception {:+}", ctx.memory.registers[runtime::runtime_types::EXCEPTION_REG]),
+                        };
+                        ("exception", Some(text))
+                    }
+                    (exit_code, _) => {
+                        let code = match exit_code {
+                            ExitCodes::End => 0,
+                            _ => 1,
+                        };
+                        self.client.event("exited", Json::object(vec![("exitCode", code.into())]));
+                        self.client.event("terminated", Json::object(vec![]));
+                        return;
+                    }
+                }
+            }
+        };
+        let mut body = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(text) = text {
+            body.push(("text", text.into()));
+        }
+        self.client.event("stopped", Json::object(body));
+    }
+
+    fn stack_trace(&self, args: &Json) -> Result<Handled, String> {
+        let ctx = self.ctx()?;
+        let trace = ctx.stack_trace();
+        let start = args.get("startFrame").and_then(Json::as_usize).unwrap_or(0);
+        let levels = match args.get("levels").and_then(Json::as_usize) {
+            Some(0) | None => trace.len(),
+            Some(levels) => levels,
+        };
+        let mut frames = vec![];
+        for (id, code_ptr) in trace.iter().enumerate().skip(start).take(levels) {
+            let mut frame = vec![
+                ("id", id.into()),
+                ("name", debugger::fun_name(ctx, *code_ptr).into()),
+                ("instructionPointerReference", code_ptr.to_string().into()),
+            ];
+            let location = ctx
+                .code
+                .debug
+                .as_ref()
+                .and_then(|debug| Some((debug, debug.location(*code_ptr)?)));
+            match location {
+                Some((debug, entry)) => {
+                    let path = debug.files.get(entry.file).cloned().unwrap_or_default();
+                    frame.push(("source", source(&path)));
+                    frame.push(("line", entry.line.into()));
+                    frame.push(("column", entry.column.into()));
+                }
+                None => {
+                    frame.push(("source", source(&self.program)));
+                    frame.push(("line", (code_ptr + 1).into()));
+                    frame.push(("column", 1.into()));
+                }
+            }
+            frames.push(Json::object(frame));
+        }
+        Ok(Handled::body(Json::object(vec![
+            ("stackFrames", frames.into()),
+            ("totalFrames", trace.len().into()),
+        ])))
+    }
+
+    fn add_ref(&mut self, var_ref: VarRef) -> usize {
+        self.refs.push(var_ref);
+        self.refs.len()
+    }
+
+    fn variables(&mut self, reference: usize) -> Result<Vec<Json>, String> {
+        let ctx = self.ctx()?;
+        // name, value
+        let values: Vec<(String, Types)> = match self.refs.get(reference.wrapping_sub(1)) {
+            Some(VarRef::Registers) => ctx
+                .memory
+                .registers
+                .iter()
+                .enumerate()
+                .map(|(reg, value)| {
+                    let name = ctx
+                        .code
+                        .debug
+                        .as_ref()
+                        .and_then(|debug| debug.register_name(ctx.code.ptr, reg));
+                    match name {
+                        Some(name) => (format!("reg({reg}) {name}"), *value),
+                        None => (format!("reg({reg})"), *value),
+                    }
+                })
+                .collect(),
+            Some(VarRef::Frame(cs_ptr, fun)) => {
+                let stack = &ctx.memory.stack;
+                let end = stack.call_stack[*cs_ptr].end;
+                let start = match cs_ptr {
+                    0 => 0,
+                    ptr => stack.call_stack[ptr - 1].end,
+                };
+                (1..=end.saturating_sub(start))
+                    .rev()
+                    .filter_map(|offset| {
+                        let value = *stack.data.get(end - offset)?;
+                        let name = match (&ctx.code.debug, fun) {
+                            (Some(debug), Some(fun)) => debug.local_name(*fun, offset),
+                            _ => None,
+                        };
+                        match name {
+                            Some(name) => Some((name.to_string(), value)),
+                            None => Some((format!("[{offset}]"), value)),
+                        }
+                    })
+                    .collect()
+            }
+            Some(VarRef::Heap) => (0..ctx.memory.heap.data.len())
+                .filter(|idx| !ctx.memory.heap.garbage.contains(idx))
+                .map(|idx| (format!("#{idx}"), Types::Pointer(idx, PointerTypes::Object)))
+                .collect(),
+            Some(VarRef::Object(idx)) => match ctx.memory.heap.data.get(*idx) {
+                Some(obj) => obj
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| match (i, value) {
+                        (0, Types::NonPrimitive(_)) => ("header".to_string(), *value),
+                        _ => (format!("[{i}]"), *value),
+                    })
+                    .collect(),
+                None => vec![],
+            },
+            None => return Err("Invalid variables reference".to_string()),
+        };
+        let mut res = Vec::with_capacity(values.len());
+        for (name, value) in values {
+            let (text, object) = self.describe(value);
+            let reference = match object {
+                Some(idx) => self.add_ref(VarRef::Object(idx)),
+                None => 0,
+            };
+            res.push(Json::object(vec![
+                ("name", name.into()),
+                ("value", text.into()),
+                ("type", format!("{value:#}").into()),
+                ("variablesReference", reference.into()),
+            ]));
+        }
+        Ok(res)
+    }
+
+    /// text of value and heap object it can be expanded into
+    fn describe(&self, value: Types) -> (String, Option<usize>) {
+        let ctx = match &self.ctx {
+            Some(ctx) => ctx,
+            None => return (format!("{value:+}"), None),
+        };
+        let memory = &ctx.memory;
+        let object = match value {
+            Types::Pointer(idx, PointerTypes::Object | PointerTypes::Heap(_)) => Some(idx),
+            Types::Closure(_, env) => Some(env),
+            Types::Pointer(idx, PointerTypes::String) if idx < memory.strings.pool.len() => {
+                return (format!("{:?}", memory.strings.to_string(idx)), None);
+            }
+            _ => None,
+        }
+        .filter(|idx| *idx < memory.heap.data.len());
+        let name = match value {
+            Types::NonPrimitive(id) => memory.non_primitives.get(id).map(|np| np.name.as_str()),
+            Types::Pointer(_, PointerTypes::Object) => memory
+                .type_id(value)
+                .and_then(|id| memory.non_primitives.get(id))
+                .map(|np| np.name.as_str()),
+            _ => None,
+        };
+        match name {
+            Some(name) => (format!("{name} {value:+}"), object),
+            None => (format!("{value:+}"), object),
+        }
+    }
+
+    /// redirects program output into output events so it does not corrupt the protocol
+    #[cfg(unix)]
+    fn capture_output(&mut self) {
+        use std::{fs::File, os::fd::FromRawFd};
+        let mut fds = [0; 2];
+        // SAFETY: file descriptors are created here and owned by the files below
+        let (dap, mut reader) = unsafe {
+            let dap = libc::dup(libc::STDOUT_FILENO);
+            if dap < 0 || libc::pipe(fds.as_mut_ptr()) < 0 {
+                return;
+            }
+            libc::dup2(fds[1], libc::STDOUT_FILENO);
+            libc::close(fds[1]);
+            (File::from_raw_fd(dap), File::from_raw_fd(fds[0]))
+        };
+        *self.client.out.lock().unwrap() = Box::new(dap);
+        let client = self.client.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(len @ 1..) = reader.read(&mut buf) {
+                let output = String::from_utf8_lossy(&buf[..len]).to_string();
+                client.event(
+                    "output",
+                    Json::object(vec![("category", "stdout".into()), ("output", output.into())]),
+                );
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn capture_output(&mut self) {}
+}
+
+/// response body and what to do after sending the response
+struct Handled {
+    body: Json,
+    then: Then,
+}
+
+enum Then {
+    Nothing,
+    Initialized,
+    /// None means stopped on entry
+    Stopped(Option<Stop>),
+    Exit,
+}
+
+impl Handled {
+    fn body(body: Json) -> Self {
+        Self {
+            body,
+            then: Then::Nothing,
+        }
+    }
+    fn then(then: Then) -> Self {
+        Self {
+            body: Json::object(vec![]),
+            then,
+        }
+    }
+}
+
+fn source(path: &str) -> Json {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().to_string());
+    Json::object(vec![("name", name.into()), ("path", path.into())])
+}
+
+/// reads one message with Content-Length header, None on end of input
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; len?];
+    input.read_exact(&mut body).ok()?;
+    String::from_utf8(body).ok()
+}
@@ -0,0 +1,525 @@
+//! Interactive command-line debugger, enabled with --debug
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use runtime::runtime_types::{
+    Context, Instructions, Types, WatchLoc, Watchpoint, FREEZED_REG_SIZE, REGISTER_SIZE,
+};
+
+use crate::timetravel::{History, Loc};
+
+const HELP: &str = "Commands:
+  b, break <idx|fun>     set breakpoint at instruction index or function
+  d, delete <idx|fun>    delete breakpoint
+  bl                     list breakpoints
+  s, step                execute one instruction
+  n, next                execute one instruction, stepping over calls
+  c, continue            run until breakpoint or end of program
+  f, finish              run until current frame returns
+  rs, rstep              go back by one instruction
+  rc, rcontinue          run back to breakpoint or start of history
+  rb, rbranch            run back to last executed Brnc
+  rw, rwrite <loc>       run back to last change of location, loc is one of
+                         reg <reg>, heap <obj> <idx>, stack <offset>, str <idx>
+  watch <loc> [log]      stop or log when location changes, loc is one of
+                         heap <obj> <idx>, stack <offset>, str <idx>
+  unwatch <n>            delete watchpoint
+  wl                     list watchpoints
+  w, where               show current instruction
+  bt                     show backtrace
+  r, regs [reg]          show registers
+  set <reg> <value>      set register (5, 5u, 1.5, 'c', true, null)
+  st, stack              show stack slots of current frame
+  heap <idx>             show heap object
+  str <idx>              show string
+  catches                show catch stack
+  h, help                show this message
+  q, quit                exit debugger
+Empty line repeats last command.";
+
+pub struct Debugger<'a> {
+    ctx: &'a mut Context,
+    history: History,
+    breakpoints: HashSet<usize>,
+    /// program reached its end and can only be inspected
+    finished: bool,
+}
+
+impl<'a> Debugger<'a> {
+    /// history is the most instructions that can be undone
+    pub fn new(ctx: &'a mut Context, history: usize) -> Self {
+        Self {
+            ctx,
+            history: History::new(history),
+            breakpoints: HashSet::new(),
+            finished: false,
+        }
+    }
+
+    pub fn run(&mut self) {
+        println!("Ruda debugger, type 'help' for list of commands.");
+        self.show_where();
+        let mut last = String::new();
+        loop {
+            print!("(rdb) ");
+            io::stdout().flush().unwrap();
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let line = match line.trim() {
+                "" => last.clone(),
+                line => line.to_string(),
+            };
+            let mut words = line.split_whitespace();
+            let cmd = match words.next() {
+                Some(cmd) => cmd,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+            match cmd {
+                "b" | "break" => self.set_breakpoint(&args, true),
+                "d" | "delete" => self.set_breakpoint(&args, false),
+                "bl" => {
+                    let mut breakpoints: Vec<_> = self.breakpoints.iter().collect();
+                    breakpoints.sort();
+                    for ptr in breakpoints {
+                        println!("{ptr}: {}", self.describe(*ptr));
+                    }
+                }
+                "s" | "step" => {
+                    if self.can_run() {
+                        let stop = single_step(self.ctx, &mut self.history);
+                        self.report(stop);
+                    }
+                }
+                "n" | "next" => {
+                    if self.can_run() {
+                        let stop = step_over(self.ctx, &mut self.history, &self.breakpoints);
+                        self.report(stop);
+                    }
+                }
+                "c" | "continue" => {
+                    if self.can_run() {
+                        let stop = run_until(self.ctx, &mut self.history, &self.breakpoints, |_| false);
+                        self.report(stop);
+                    }
+                }
+                "f" | "finish" => {
+                    if self.can_run() {
+                        let stop = step_out(self.ctx, &mut self.history, &self.breakpoints);
+                        self.report(stop);
+                    }
+                }
+                "rs" | "rstep" => {
+                    let moved = self.history.step_back(self.ctx);
+                    self.report_back(moved, "Start of history reached.");
+                }
+                "rc" | "rcontinue" => {
+                    let breakpoints = &self.breakpoints;
+                    match self.history.run_back(self.ctx, |ptr| breakpoints.contains(&ptr)) {
+                        true => println!("Breakpoint {}", self.ctx.code.ptr),
+                        false => println!("Start of history reached."),
+                    }
+                    self.report_back(true, "");
+                }
+                "rb" | "rbranch" => {
+                    let moved = self.history.branch_back(self.ctx);
+                    self.report_back(moved, "No Brnc in history.");
+                }
+                "rw" | "rwrite" => {
+                    let loc = match args.split_first() {
+                        Some((&"reg", [reg])) => match reg.parse::<usize>() {
+                            Ok(reg) if reg < REGISTER_SIZE => Ok(Loc::Register(reg)),
+                            _ => Err("Invalid register.".to_string()),
+                        },
+                        _ => self.memory_loc(&args).map(Loc::Memory),
+                    };
+                    match loc {
+                        Ok(loc) => {
+                            let moved = self.history.write_back(self.ctx, loc);
+                            self.report_back(moved, "No change of location in history.");
+                        }
+                        Err(err) => println!("{err}"),
+                    }
+                }
+                "watch" => self.watch(&args),
+                "unwatch" => match parse_idx(&args) {
+                    Some(idx) if idx < self.ctx.watchpoints.len() => {
+                        let watchpoint = self.ctx.watchpoints.remove(idx);
+                        println!("Watchpoint deleted at {}", watchpoint.loc);
+                    }
+                    _ => println!("Usage: unwatch <n>"),
+                },
+                "wl" => {
+                    for (i, watchpoint) in self.ctx.watchpoints.iter().enumerate() {
+                        let mode = if watchpoint.stop { "stop" } else { "log" };
+                        let value = self.ctx.memory.watch_value(watchpoint.loc);
+                        println!("{i}: {} ({mode}) = {}", watchpoint.loc, value.as_deref().unwrap_or("<none>"));
+                    }
+                }
+                "w" | "where" => self.show_where(),
+                "bt" => println!("{}", self.ctx.format_trace(&self.ctx.stack_trace())),
+                "r" | "regs" => self.show_registers(&args),
+                "set" => self.set_register(&args),
+                "st" | "stack" => self.show_stack(),
+                "heap" => match parse_idx(&args) {
+                    Some(idx) if idx < self.ctx.memory.heap.data.len() => {
+                        for (i, value) in self.ctx.memory.heap.data[idx].iter().enumerate() {
+                            println!("[{i}] {value:+}");
+                        }
+                    }
+                    _ => println!("Usage: heap <idx>, heap has {} objects", self.ctx.memory.heap.data.len()),
+                },
+                "str" => match parse_idx(&args) {
+                    Some(idx) if idx < self.ctx.memory.strings.pool.len() => {
+                        println!("{:?}", self.ctx.memory.strings.to_string(idx));
+                    }
+                    _ => println!("Usage: str <idx>, pool has {} strings", self.ctx.memory.strings.pool.len()),
+                },
+                "catches" => {
+                    for (i, catch) in self.ctx.catches.cache.iter().enumerate().rev() {
+                        let kind = match (catch.finally, catch.id) {
+                            (true, _) => "finally".to_string(),
+                            (false, Some(id)) => format!("catch {id}"),
+                            (false, None) => "catch".to_string(),
+                        };
+                        println!("#{i} {kind} at {} (frame {})", catch.code_ptr, catch.cs_ptr);
+                    }
+                }
+                "h" | "help" => println!("{HELP}"),
+                "q" | "quit" => return,
+                _ => println!("Unknown command '{cmd}', type 'help' for list of commands."),
+            }
+            last = line;
+        }
+    }
+
+    fn can_run(&self) -> bool {
+        if self.finished {
+            println!("Program has ended.");
+        }
+        !self.finished
+    }
+
+    /// reports position after going back, message is printed when history did not move
+    fn report_back(&mut self, moved: bool, message: &str) {
+        if !moved {
+            println!("{message}");
+            return;
+        }
+        self.finished = false;
+        self.show_where();
+    }
+
+    fn report(&mut self, stop: Stop) {
+        match stop {
+            Stop::Step => self.show_where(),
+            Stop::Breakpoint => {
+                println!("Breakpoint {}", self.ctx.code.ptr);
+                self.show_where();
+            }
+            Stop::Watchpoint => {
+                for hit in self.ctx.watch_hits.drain(..) {
+                    let loc = self.ctx.watchpoints[hit.watchpoint].loc;
+                    println!("Watchpoint {} {loc}: {hit}", hit.watchpoint);
+                }
+                self.show_where();
+            }
+            Stop::Ended => {
+                self.finished = true;
+                println!("Program ended with {:?}", self.ctx.exit_code);
+            }
+        }
+    }
+
+    /// memory location from arguments: heap <obj> <idx>, stack <offset> or str <idx>
+    fn memory_loc(&self, args: &[&str]) -> Result<WatchLoc, String> {
+        let parsed: Vec<usize> = args.iter().skip(1).filter_map(|arg| arg.parse().ok()).collect();
+        match (args.first(), parsed.as_slice()) {
+            (Some(&"heap"), [obj, idx]) => Ok(WatchLoc::Heap(*obj, *idx)),
+            (Some(&"stack"), [offset]) => match self.ctx.stack_end().checked_sub(*offset) {
+                Some(idx) => Ok(WatchLoc::Stack(idx)),
+                None => Err(format!("Offset {offset} is outside of the stack.")),
+            },
+            (Some(&"str"), [idx]) => Ok(WatchLoc::String(*idx)),
+            _ => Err("Expected heap <obj> <idx>, stack <offset> or str <idx>.".to_string()),
+        }
+    }
+
+    fn watch(&mut self, args: &[&str]) {
+        let (args, stop) = match args.split_last() {
+            Some((&"log", args)) => (args, false),
+            _ => (args, true),
+        };
+        let loc = match self.memory_loc(args) {
+            Ok(loc) => loc,
+            Err(_) => {
+                println!("Usage: watch heap <obj> <idx> | stack <offset> | str <idx> [log]");
+                return;
+            }
+        };
+        let value = self.ctx.memory.watch_value(loc);
+        println!(
+            "Watchpoint {} set at {loc} = {}",
+            self.ctx.watchpoints.len(),
+            value.as_deref().unwrap_or("<none>")
+        );
+        self.ctx.watchpoints.push(Watchpoint { loc, stop });
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str], set: bool) {
+        let target = match args.first() {
+            Some(target) => *target,
+            None => {
+                println!("Usage: break <idx|fun>");
+                return;
+            }
+        };
+        let locs: Vec<usize> = match target.parse::<usize>() {
+            Ok(idx) => vec![idx],
+            Err(_) => fun_locs(self.ctx, target),
+        };
+        if locs.is_empty() {
+            println!("Function '{target}' not found.");
+        }
+        for loc in locs {
+            if loc >= self.ctx.code.data.len() {
+                println!("Instruction {loc} is out of range.");
+            } else if set {
+                self.breakpoints.insert(loc);
+                println!("Breakpoint set at {loc}: {}", self.describe(loc));
+            } else if self.breakpoints.remove(&loc) {
+                println!("Breakpoint deleted at {loc}");
+            }
+        }
+    }
+
+    /// instruction at code_ptr together with its function and source location
+    fn describe(&self, code_ptr: usize) -> String {
+        let name = fun_name(self.ctx, code_ptr);
+        let instr = match self.ctx.code.data.get(code_ptr) {
+            Some(instr) => format!("{instr:?}"),
+            None => "<out of code>".to_string(),
+        };
+        match self.ctx.location(code_ptr) {
+            Some(location) => format!("{instr} in {name} ({location})"),
+            None => format!("{instr} in {name}"),
+        }
+    }
+
+    fn show_where(&self) {
+        println!("{}: {}", self.ctx.code.ptr, self.describe(self.ctx.code.ptr));
+    }
+
+    fn register_name(&self, reg: usize) -> String {
+        let annotation = self
+            .ctx
+            .code
+            .debug
+            .as_ref()
+            .and_then(|debug| debug.register_name(self.ctx.code.ptr, reg));
+        match annotation {
+            Some(name) => format!("reg({reg}) {name}"),
+            None => format!("reg({reg})"),
+        }
+    }
+
+    fn show_registers(&self, args: &[&str]) {
+        match parse_idx(args) {
+            Some(reg) if reg < REGISTER_SIZE => {
+                println!("{} = {:+}", self.register_name(reg), self.ctx.memory.registers[reg]);
+            }
+            Some(_) => println!("There are only {REGISTER_SIZE} registers."),
+            None => {
+                for reg in 0..REGISTER_SIZE {
+                    let freezed = if reg < FREEZED_REG_SIZE { " (freezed)" } else { "" };
+                    println!(
+                        "{}{freezed} = {:+}",
+                        self.register_name(reg),
+                        self.ctx.memory.registers[reg]
+                    );
+                }
+            }
+        }
+    }
+
+    fn set_register(&mut self, args: &[&str]) {
+        let (reg, value) = match args {
+            [reg, value] => (reg.parse::<usize>(), parse_value(value)),
+            _ => {
+                println!("Usage: set <reg> <value>");
+                return;
+            }
+        };
+        match (reg, value) {
+            (Ok(reg), Some(value)) if reg < REGISTER_SIZE => {
+                self.ctx.memory.registers[reg] = value;
+                self.history.modified(self.ctx);
+                println!("{} = {value:+}", self.register_name(reg));
+            }
+            (Ok(_), Some(_)) | (Err(_), _) => println!("Invalid register."),
+            (_, None) => println!("Invalid value."),
+        }
+    }
+
+    fn show_stack(&self) {
+        let stack = &self.ctx.memory.stack;
+        let end = self.ctx.stack_end();
+        let start = match stack.ptr {
+            0 => 0,
+            ptr => stack.call_stack[ptr - 1].end,
+        };
+        let fun = self.ctx.memory.fun_at(self.ctx.code.ptr);
+        println!("Frame {} ({}..{end})", stack.ptr, start);
+        for offset in (1..=end.saturating_sub(start)).rev() {
+            let name = match (&self.ctx.code.debug, fun) {
+                (Some(debug), Some(fun)) => debug.local_name(fun, offset),
+                _ => None,
+            };
+            let value = match stack.data.get(end - offset) {
+                Some(value) => format!("{value:+}"),
+                None => "<unallocated>".to_string(),
+            };
+            match name {
+                Some(name) => println!("[{offset}] {name} = {value}"),
+                None => println!("[{offset}] {value}"),
+            }
+        }
+    }
+}
+
+/// why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// requested step finished
+    Step,
+    Breakpoint,
+    /// stopping watchpoint changed, changes are in Context.watch_hits
+    Watchpoint,
+    Ended,
+}
+
+/// executes one instruction, same as one iteration of Context::run; returns false if program ended
+pub fn step(ctx: &mut Context) -> bool {
+    let running = ctx.read_line();
+    ctx.memory.gc_sweep_unoptimized();
+    running
+}
+
+/// executes one instruction and reports why execution stopped
+pub fn single_step(ctx: &mut Context, history: &mut History) -> Stop {
+    if !history.step(ctx) {
+        return Stop::Ended;
+    }
+    if !ctx.watch_hits.is_empty() {
+        return Stop::Watchpoint;
+    }
+    Stop::Step
+}
+
+/// runs until stop returns true, breakpoint or watchpoint is hit or program ends
+pub fn run_until(
+    ctx: &mut Context,
+    history: &mut History,
+    breakpoints: &HashSet<usize>,
+    stop: impl Fn(&Context) -> bool,
+) -> Stop {
+    loop {
+        match single_step(ctx, history) {
+            Stop::Step => {}
+            stop => return stop,
+        }
+        if breakpoints.contains(&ctx.code.ptr) {
+            return Stop::Breakpoint;
+        }
+        if stop(ctx) {
+            return Stop::Step;
+        }
+    }
+}
+
+/// executes one instruction, calls are executed until they return
+pub fn step_over(ctx: &mut Context, history: &mut History, breakpoints: &HashSet<usize>) -> Stop {
+    use Instructions::*;
+    match ctx.code.data[ctx.code.ptr] {
+        Jump(_) | Mtd(_, _, _) | Gotop | Call(_) | CallDyn(_) => {
+            let ptr = ctx.code.ptr + 1;
+            let depth = ctx.memory.stack.ptr;
+            run_until(ctx, history, breakpoints, |ctx| {
+                ctx.code.ptr == ptr && ctx.memory.stack.ptr <= depth
+            })
+        }
+        _ => single_step(ctx, history),
+    }
+}
+
+/// runs until current frame returns
+pub fn step_out(ctx: &mut Context, history: &mut History, breakpoints: &HashSet<usize>) -> Stop {
+    let depth = ctx.memory.stack.ptr;
+    run_until(ctx, history, breakpoints, |ctx| ctx.memory.stack.ptr < depth)
+}
+
+/// locations of all functions with given name
+pub fn fun_locs(ctx: &Context, name: &str) -> Vec<usize> {
+    ctx.memory
+        .fun_table
+        .iter()
+        .filter(|fun| fun.name == name)
+        .map(|fun| fun.loc)
+        .collect()
+}
+
+/// name of function containing instruction at code_ptr
+pub fn fun_name(ctx: &Context, code_ptr: usize) -> &str {
+    match ctx.memory.fun_at(code_ptr) {
+        Some(fun) => ctx.memory.fun_table[fun].name.as_str(),
+        None => "<main>",
+    }
+}
+
+/// parses watch location from command line: heap:<obj>:<idx>, stack:<idx> or string:<idx>
+pub fn parse_watch(str: &str) -> Result<WatchLoc, String> {
+    let parts: Vec<&str> = str.split(':').collect();
+    let nums: Vec<usize> = parts[1..].iter().filter_map(|num| num.parse().ok()).collect();
+    match (parts[0], nums.as_slice()) {
+        ("heap", [obj, idx]) if parts.len() == 3 => Ok(WatchLoc::Heap(*obj, *idx)),
+        ("stack", [idx]) if parts.len() == 2 => Ok(WatchLoc::Stack(*idx)),
+        ("string", [idx]) if parts.len() == 2 => Ok(WatchLoc::String(*idx)),
+        _ => Err(format!(
+            "Invalid watch location '{str}', expected heap:<obj>:<idx>, stack:<idx> or string:<idx>"
+        )),
+    }
+}
+
+fn parse_idx(args: &[&str]) -> Option<usize> {
+    args.first()?.parse().ok()
+}
+
+/// parses value in the same notation as Debug prints it, without the type
+pub fn parse_value(str: &str) -> Option<Types> {
+    match str {
+        "true" => return Some(Types::Bool(true)),
+        "false" => return Some(Types::Bool(false)),
+        "null" => return Some(Types::Null),
+        _ => {}
+    }
+    if let Some(usize) = str.strip_suffix('u') {
+        return usize.parse().ok().map(Types::Usize);
+    }
+    if str.len() >= 3 && str.starts_with('\'') && str.ends_with('\'') {
+        let mut chars = str[1..str.len() - 1].chars();
+        return match (chars.next(), chars.next()) {
+            (Some(char), None) => Some(Types::Char(char)),
+            _ => None,
+        };
+    }
+    if let Ok(int) = str.parse() {
+        return Some(Types::Int(int));
+    }
+    str.parse().ok().map(Types::Float)
+}
@@ -0,0 +1,263 @@
+//! Minimal JSON value with parser and serializer, used by the debug adapter
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// kept apart from Number so that integers above 2^53 are not rounded
+    Integer(i128),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// keeps order of keys
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// builds object from key value pairs
+    pub fn object(pairs: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+    /// returns value of key if this is an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(str) => Some(str),
+            _ => None,
+        }
+    }
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Integer(num) => usize::try_from(*num).ok(),
+            Json::Number(num) if *num >= 0.0 && num.fract() == 0.0 => Some(*num as usize),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(arr) => Some(arr),
+            _ => None,
+        }
+    }
+    pub fn parse(str: &str) -> Result<Json, String> {
+        let mut chars = str.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(char) => Err(format!("Unexpected character '{char}' after value")),
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(str: &str) -> Self {
+        Json::String(str.to_string())
+    }
+}
+impl From<String> for Json {
+    fn from(str: String) -> Self {
+        Json::String(str)
+    }
+}
+impl From<usize> for Json {
+    fn from(num: usize) -> Self {
+        Json::Integer(num as i128)
+    }
+}
+impl From<bool> for Json {
+    fn from(bool: bool) -> Self {
+        Json::Bool(bool)
+    }
+}
+impl From<Vec<Json>> for Json {
+    fn from(arr: Vec<Json>) -> Self {
+        Json::Array(arr)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(bool) => write!(f, "{bool}"),
+            Json::Integer(num) => write!(f, "{num}"),
+            Json::Number(num) if num.is_finite() => write!(f, "{num}"),
+            // JSON has no NaN or infinity
+            Json::Number(num) => write_str(&num.to_string(), f),
+            Json::String(str) => write_str(str, f),
+            Json::Array(arr) => {
+                write!(f, "[")?;
+                for (i, value) in arr.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(key, f)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_str(str: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "\"")?;
+    for char in str.chars() {
+        match char {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            char if (char as u32) < 0x20 => write!(f, "\\u{:04x}", char as u32)?,
+            char => write!(f, "{char}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while let Some(' ' | '\n' | '\r' | '\t') = chars.peek() {
+        chars.next();
+    }
+}
+
+fn expect_word(chars: &mut Chars, word: &str, value: Json) -> Result<Json, String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("Expected '{word}'"));
+        }
+    }
+    Ok(value)
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Json, String> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('n') => expect_word(chars, "null", Json::Null),
+        Some('t') => expect_word(chars, "true", Json::Bool(true)),
+        Some('f') => expect_word(chars, "false", Json::Bool(false)),
+        Some('"') => Ok(Json::String(parse_str(chars)?)),
+        Some('[') => {
+            chars.next();
+            let mut arr = Vec::new();
+            skip_whitespace(chars);
+            if let Some(']') = chars.peek() {
+                chars.next();
+                return Ok(Json::Array(arr));
+            }
+            loop {
+                arr.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Json::Array(arr)),
+                    _ => return Err("Expected ',' or ']'".to_string()),
+                }
+            }
+        }
+        Some('{') => {
+            chars.next();
+            let mut pairs = Vec::new();
+            skip_whitespace(chars);
+            if let Some('}') = chars.peek() {
+                chars.next();
+                return Ok(Json::Object(pairs));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_str(chars)?;
+                skip_whitespace(chars);
+                if chars.next() != Some(':') {
+                    return Err("Expected ':'".to_string());
+                }
+                pairs.push((key, parse_value(chars)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some('}') => return Ok(Json::Object(pairs)),
+                    _ => return Err("Expected ',' or '}'".to_string()),
+                }
+            }
+        }
+        Some('-' | '0'..='9') => {
+            let mut num = String::new();
+            while let Some(char @ ('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) = chars.peek() {
+                num.push(*char);
+                chars.next();
+            }
+            let integer = !num.contains(['.', 'e', 'E']);
+            match integer {
+                true => num.parse().map(Json::Integer).ok(),
+                false => None,
+            }
+            .or_else(|| num.parse().map(Json::Number).ok())
+            .ok_or_else(|| format!("Invalid number '{num}'"))
+        }
+        Some(char) => Err(format!("Unexpected character '{char}'")),
+        None => Err("Unexpected end of input".to_string()),
+    }
+}
+
+fn parse_str(chars: &mut Chars) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("Expected string".to_string());
+    }
+    let mut str = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(str),
+            Some('\\') => match chars.next() {
+                Some('"') => str.push('"'),
+                Some('\\') => str.push('\\'),
+                Some('/') => str.push('/'),
+                Some('b') => str.push('\u{8}'),
+                Some('f') => str.push('\u{c}'),
+                Some('n') => str.push('\n'),
+                Some('r') => str.push('\r'),
+                Some('t') => str.push('\t'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("Invalid escape '\\u{hex}'"))?;
+                    // surrogate pairs are replaced, DAP clients rarely send them
+                    str.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                _ => return Err("Invalid escape sequence".to_string()),
+            },
+            Some(char) => str.push(char),
+            None => return Err("Unterminated string".to_string()),
+        }
+    }
+}
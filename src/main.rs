@@ -2,14 +2,36 @@ extern crate runtime;
 use enable_ansi_support::enable_ansi_support;
 use runtime::runtime_types::*;
 use stringify::ShLib;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+mod allocations;
+mod checkpoint;
+mod coverage;
+mod dap;
+mod debugger;
+mod json;
+mod profile;
+mod replay;
+mod report;
 mod stringify;
+mod timetravel;
+mod trace;
 
 mod test;
 
 use clap::Parser;
 
+/// flags that choose how the program runs, only flags of one mode can be used together
+const MODES: [&str; 7] = [
+    "trace",
+    "profile",
+    "profile_folded",
+    "alloc_profile",
+    "alloc_timeline",
+    "coverage",
+    "checkpoint",
+];
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "Ruda VM",
@@ -26,10 +48,107 @@ struct Args {
     #[clap(short, long, default_value = "false")]
     report: bool,
 
+    /// Format of post-process data report, json includes frames, GC statistics, exit code and run time
+    #[clap(long, value_parser = ["text", "json"], default_value = "text")]
+    report_format: String,
+
+    /// Write data report to file instead of stdout
+    #[clap(long)]
+    report_output: Option<String>,
+
+    /// Write graph of heap objects and strings to file in Graphviz DOT format after run
+    #[clap(long)]
+    heap_dot: Option<String>,
+
     /// Measure runtime
     #[clap(short, long, default_value = "false")]
     time: bool,
 
+    /// Run in interactive debugger
+    #[clap(short, long, default_value = "false", conflicts_with_all = MODES)]
+    debug: bool,
+
+    /// Run as Debug Adapter Protocol server over stdio
+    #[clap(long, default_value = "false", conflicts_with_all = MODES, conflicts_with = "debug")]
+    dap: bool,
+
+    /// Number of instructions that can be undone in debugger, 0 disables reverse execution
+    #[clap(long, default_value_t = 1_000_000)]
+    history: usize,
+
+    /// Watch memory location and log its changes (stops in debugger):
+    /// heap:<obj>:<idx>, stack:<idx> or string:<idx>
+    #[clap(long, value_parser = debugger::parse_watch)]
+    watch: Vec<runtime::runtime_types::WatchLoc>,
+
+    /// Write trace of executed instructions to file as JSON Lines
+    #[clap(long, conflicts_with_all = &MODES[1..])]
+    trace: Option<String>,
+
+    /// Trace only instructions in range <start>..<end> (repeatable)
+    #[clap(long, value_parser = trace::parse_range, requires = "trace")]
+    trace_range: Vec<(usize, usize)>,
+
+    /// Trace only instructions of function (repeatable)
+    #[clap(long, requires = "trace")]
+    trace_fun: Vec<String>,
+
+    /// Trace only instructions of kind, e.g. Add or Call (repeatable)
+    #[clap(long, requires = "trace")]
+    trace_op: Vec<String>,
+
+    /// Print profile of instructions, functions, native calls and GC after run
+    #[clap(long, default_value = "false", conflicts_with_all = &MODES[3..])]
+    profile: bool,
+
+    /// Profile and write folded stacks (nanoseconds) to file for flamegraph tools
+    #[clap(long, conflicts_with_all = &MODES[3..])]
+    profile_folded: Option<String>,
+
+    /// Print allocation sites and longest retained objects after run
+    #[clap(long, default_value = "false", conflicts_with_all = &MODES[5..])]
+    alloc_profile: bool,
+
+    /// Track allocations and write heap and string pool sizes over time to file as CSV
+    #[clap(long, conflicts_with_all = &MODES[5..])]
+    alloc_timeline: Option<String>,
+
+    /// Write coverage of instructions and branches to file in lcov format
+    #[clap(long, conflicts_with_all = &MODES[6..])]
+    coverage: Option<String>,
+
+    /// Write checkpoint of the running VM to file every --checkpoint-every instructions
+    #[clap(long)]
+    checkpoint: Option<String>,
+
+    /// Number of instructions between checkpoints
+    #[clap(long, default_value_t = 1_000_000)]
+    checkpoint_every: usize,
+
+    /// Resume execution from checkpoint file instead of starting a program
+    #[clap(long)]
+    resume: Option<String>,
+
+    /// Record results of native calls and memory they changed to file
+    #[clap(long)]
+    record: Option<String>,
+
+    /// Replay native calls from recording instead of loading libraries
+    #[clap(long)]
+    replay: Option<String>,
+
+    /// Maximum depth of the call stack
+    #[clap(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_call_depth: usize,
+
+    /// Maximum number of nested catch blocks
+    #[clap(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_catch_depth: usize,
+
+    /// Run test scenario with given id when no input file is given
+    #[clap(long)]
+    test: Option<usize>,
+
     /// Runtime arguments for the VM
     #[clap(name = "args", last = true)]
     args: Vec<String>,
@@ -38,74 +157,294 @@ struct Args {
 fn main() {
     let args = Args::parse();
     let mut report = args.report;
-    let mut ctx = match args.input {
-        Some(src) => {
-            let ruda_path = std::env::var("RUDA_PATH").unwrap();
-            let file = std::fs::read(&src).unwrap();
-            let mut ctx = Context::new();
-            let mut data = stringify::parse(&String::from_utf8(file).unwrap());
-            ctx.memory.stack.data = data.values;
-            ctx.memory.strings.pool = data.strings;
-            ctx.code.data = data.instructions;
-            ctx.memory.non_primitives = data.non_primitives;
-            ctx.memory.fun_table = data.fun_table;
-            data.shared_libs = vec![
-                ShLib { path: "io".to_string(), owns: stringify::LibOwner::Standard},
-                ShLib { path: "string".to_string(), owns: stringify::LibOwner::Standard},
-                ShLib { path: "fs".to_string(), owns: stringify::LibOwner::Standard},
-            ];
-            for lib in &data.shared_libs {
-                ctx.libs.push(test::test::load_lib(&lib.into_real_path(&src, &ruda_path)));
+    if args.dap {
+        dap::Server::new(args.max_call_depth, args.max_catch_depth, args.history).serve();
+        return;
+    }
+    // source file of coverage report when program has no debug info
+    let program = args.input.clone().unwrap_or_else(|| "test.rdbin".to_string());
+    // libraries written to checkpoints
+    let (mut ctx, shared_libs) = match (args.resume, args.input) {
+        (Some(path), input) => {
+            match resume(&path, input.as_deref(), args.max_call_depth, args.max_catch_depth) {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    println!("Failed to resume from '{path}': {err}");
+                    return;
+                }
             }
-            ctx
         }
-        None => {
+        (None, Some(src)) => {
+            let loaded = match args.replay.is_some() {
+                true => load_program(&src, args.max_call_depth, args.max_catch_depth)
+                    .map(|ctx| (ctx, Vec::new())),
+                false => load(&src, args.max_call_depth, args.max_catch_depth),
+            };
+            match loaded {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    println!("Failed to load '{src}': {err}");
+                    return;
+                }
+            }
+        }
+        (None, None) => {
             /*println!("Path not specified. Program will terminate."); return;*/
             use test::test::*;
-            let mut ctx = Context::new();
-            report = test_init(None, &mut ctx);
-            let stringified = stringify::stringify(&ctx);
+            let mut ctx = Context::with_limits(args.max_call_depth, args.max_catch_depth);
+            report = test_init(args.test, &mut ctx);
+            ctx.memory.stack.globals = ctx.memory.stack.data.len();
+            let stringified = stringify::stringify(&ctx, &[]);
             // write to file
             std::fs::write("test.rdbin", stringified).unwrap();
-            ctx
+            (ctx, Vec::new())
         }
     };
-    ctx.memory.runtime_args = args.args;
-    match args.time {
-        true => {
-            let start_time = SystemTime::now();
-            ctx.run();
-            match enable_ansi_support() {
-                Ok(_) => {
-                    println!(
-                        "\x1b[90mTotal run time: {} ms\x1b[0m",
-                        SystemTime::now()
-                            .duration_since(start_time)
-                            .unwrap()
-                            .as_millis()
-                    );
-                }
-                Err(_) => {
-                    println!(
-                        "Total run time: {} ms",
-                        SystemTime::now()
-                            .duration_since(start_time)
-                            .unwrap()
-                            .as_millis()
-                    );
+    // resumed programs keep their arguments unless new ones are given
+    if !args.args.is_empty() || ctx.memory.runtime_args.is_empty() {
+        ctx.memory.runtime_args = args.args;
+    }
+    if let Some(path) = &args.replay {
+        let recording = std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| String::from_utf8(file).map_err(|err| err.to_string()))
+            .and_then(|file| replay::Recording::parse(&file));
+        match recording {
+            Ok(recording) => ctx.libs = replay::replay(recording),
+            Err(err) => {
+                println!("Failed to load recording from '{path}': {err}");
+                return;
+            }
+        }
+    }
+    let recording = match args.record {
+        Some(path) => {
+            let (libs, recording) = replay::record(std::mem::take(&mut ctx.libs));
+            ctx.libs = libs;
+            Some((path, recording))
+        }
+        None => None,
+    };
+    for loc in args.watch {
+        ctx.watchpoints.push(Watchpoint { loc, stop: args.debug });
+    }
+    // at most one mode is given, flags of different modes conflict
+    let mode = match (args.trace, args.profile || args.profile_folded.is_some()) {
+        _ if args.coverage.is_some() => Mode::Coverage(args.coverage.unwrap(), program),
+        _ if args.alloc_profile || args.alloc_timeline.is_some() => {
+            Mode::Allocations(args.alloc_timeline)
+        }
+        (Some(path), _) => Mode::Trace(
+            path,
+            trace::Filter {
+                ranges: args.trace_range,
+                funs: args.trace_fun,
+                ops: args.trace_op,
+            },
+        ),
+        (None, true) => Mode::Profile(args.profile_folded),
+        (None, false) => match args.checkpoint {
+            Some(path) => Mode::Checkpoint(path, args.checkpoint_every.max(1), shared_libs),
+            None => Mode::Run,
+        },
+    };
+    let report = match report || args.report_output.is_some() {
+        true => Some(Report {
+            json: args.report_format == "json",
+            output: args.report_output,
+        }),
+        false => None,
+    };
+    if args.debug {
+        debugger::Debugger::new(&mut ctx, args.history).run();
+        save_recording(&recording);
+        post_run(&ctx, &report, &args.heap_dot, None);
+        return;
+    }
+    let start_time = SystemTime::now();
+    run(&mut ctx, &mode);
+    let run_time = SystemTime::now().duration_since(start_time).unwrap();
+    if args.time {
+        match enable_ansi_support() {
+            Ok(_) => {
+                println!("\x1b[90mTotal run time: {} ms\x1b[0m", run_time.as_millis());
+            }
+            Err(_) => {
+                println!("Total run time: {} ms", run_time.as_millis());
+            }
+        }
+    }
+    save_recording(&recording);
+    post_run(&ctx, &report, &args.heap_dot, Some(run_time));
+}
+
+fn save_recording(recording: &Option<(String, std::rc::Rc<std::cell::RefCell<replay::Recording>>)>) {
+    if let Some((path, recording)) = recording {
+        if let Err(err) = std::fs::write(path, recording.borrow().stringify()) {
+            println!("Failed to write recording to '{path}': {err}");
+        }
+    }
+}
+
+/// where and how data report is written
+struct Report {
+    json: bool,
+    /// stdout if None
+    output: Option<String>,
+}
+
+fn post_run(ctx: &Context, report: &Option<Report>, heap_dot: &Option<String>, run_time: Option<Duration>) {
+    if let Some(path) = heap_dot {
+        if let Err(err) = std::fs::write(path, report::dot(ctx)) {
+            println!("Failed to write heap graph to '{path}': {err}");
+        }
+    }
+    let report = match report {
+        Some(report) => report,
+        None => return,
+    };
+    match (report.json, &report.output) {
+        (false, None) => data_report(ctx),
+        (false, Some(path)) => {
+            let text = format!(
+                "Heap: {:?}\nStack: {:?}\nRegisters: {:?}\nStrings: {:?}\n",
+                ctx.memory.heap.data, ctx.memory.stack.data, ctx.memory.registers, ctx.memory.strings.pool
+            );
+            if let Err(err) = std::fs::write(path, text) {
+                println!("Failed to write report to '{path}': {err}");
+            }
+        }
+        (true, None) => println!("{}", report::json(ctx, run_time)),
+        (true, Some(path)) => {
+            if let Err(err) = std::fs::write(path, format!("{}\n", report::json(ctx, run_time))) {
+                println!("Failed to write report to '{path}': {err}");
+            }
+        }
+    }
+}
+
+/// how the program is executed
+enum Mode {
+    Run,
+    /// output file, filter
+    Trace(String, trace::Filter),
+    /// output file for folded stacks
+    Profile(Option<String>),
+    /// output file, program used as source file without debug info
+    Coverage(String, String),
+    /// output file for timeline
+    Allocations(Option<String>),
+    /// output file, instructions between checkpoints, libraries of the program
+    Checkpoint(String, usize, Vec<ShLib>),
+}
+
+fn run(ctx: &mut Context, mode: &Mode) {
+    match mode {
+        Mode::Run => ctx.run(),
+        Mode::Trace(path, filter) => {
+            if let Err(err) = trace::run(ctx, path, filter) {
+                println!("Failed to write trace to '{path}': {err}");
+            }
+        }
+        Mode::Profile(folded) => {
+            let profile = profile::run(ctx);
+            print!("{}", profile.report(ctx));
+            if let Some(path) = folded {
+                if let Err(err) = std::fs::write(path, profile.folded(ctx)) {
+                    println!("Failed to write folded stacks to '{path}': {err}");
                 }
             }
-            if report {
-                data_report(&ctx);
+        }
+        Mode::Coverage(path, program) => {
+            let coverage = coverage::run(ctx);
+            if ctx.code.debug.is_none() {
+                println!("Program has no debug info, line N of coverage is instruction N - 1");
+            }
+            if let Err(err) = std::fs::write(path, coverage.lcov(ctx, program)) {
+                println!("Failed to write coverage to '{path}': {err}");
             }
         }
-        false => {
-            ctx.run();
-            if report {
-                data_report(&ctx);
+        Mode::Allocations(timeline) => {
+            let allocations = allocations::run(ctx);
+            print!("{}", allocations.report(ctx));
+            if let Some(path) = timeline {
+                if let Err(err) = std::fs::write(path, allocations.timeline_csv()) {
+                    println!("Failed to write allocation timeline to '{path}': {err}");
+                }
             }
         }
+        Mode::Checkpoint(path, every, shared_libs) => {
+            let mut steps = 0;
+            while debugger::step(ctx) {
+                steps += 1;
+                if steps % every == 0 {
+                    if let Err(err) = checkpoint::write(ctx, shared_libs, path) {
+                        println!("Failed to write checkpoint to '{path}': {err}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// loads .rdbin file together with its libraries, returns libraries that were loaded
+fn load(src: &str, max_call_depth: usize, max_catch_depth: usize) -> Result<(Context, Vec<ShLib>), String> {
+    let ruda_path = std::env::var("RUDA_PATH").map_err(|err| format!("RUDA_PATH: {err}"))?;
+    let mut ctx = load_program(src, max_call_depth, max_catch_depth)?;
+    let shared_libs = std_libs();
+    ctx.libs = load_libs(&shared_libs, src, &ruda_path)?;
+    Ok((ctx, shared_libs))
+}
+
+/// loads .rdbin file without libraries
+fn load_program(src: &str, max_call_depth: usize, max_catch_depth: usize) -> Result<Context, String> {
+    let file = std::fs::read(src).map_err(|err| err.to_string())?;
+    let mut ctx = Context::with_limits(max_call_depth, max_catch_depth);
+    let data = stringify::parse(&String::from_utf8(file).map_err(|err| err.to_string())?)?;
+    ctx.memory.stack.globals = data.values.len();
+    ctx.memory.stack.data = data.values;
+    ctx.memory.strings.pool = data.strings;
+    ctx.code.data = data.instructions;
+    ctx.memory.non_primitives = data.non_primitives;
+    ctx.memory.fun_table = data.fun_table;
+    ctx.code.debug = data.debug;
+    Ok(ctx)
+}
+
+/// libraries loaded for every binary
+fn std_libs() -> Vec<ShLib> {
+    vec![
+        ShLib { path: "io".to_string(), owns: stringify::LibOwner::Standard},
+        ShLib { path: "string".to_string(), owns: stringify::LibOwner::Standard},
+        ShLib { path: "fs".to_string(), owns: stringify::LibOwner::Standard},
+    ]
+}
+
+/// loads libraries of binary at src
+fn load_libs(shared_libs: &[ShLib], src: &str, ruda_path: &str) -> Result<Libs, String> {
+    shared_libs
+        .iter()
+        .map(|lib| test::test::load_lib(&lib.into_real_path(src, ruda_path)?))
+        .collect()
+}
+
+/// restores context from checkpoint, libraries stored in it are loaded relative to input if given
+fn resume(
+    path: &str,
+    input: Option<&str>,
+    max_call_depth: usize,
+    max_catch_depth: usize,
+) -> Result<(Context, Vec<ShLib>), String> {
+    let file = std::fs::read(path).map_err(|err| err.to_string())?;
+    let file = String::from_utf8(file).map_err(|err| err.to_string())?;
+    let mut ctx = Context::with_limits(max_call_depth, max_catch_depth);
+    let (shared_libs, libs) = checkpoint::restore(&mut ctx, &file)?;
+    if !shared_libs.is_empty() {
+        let ruda_path = std::env::var("RUDA_PATH").map_err(|err| format!("RUDA_PATH: {err}"))?;
+        ctx.libs = load_libs(&shared_libs, input.unwrap_or(path), &ruda_path)?;
     }
+    checkpoint::restore_libs(&mut ctx, &libs)?;
+    Ok((ctx, shared_libs))
 }
 
 fn data_report(ctx: &Context) {
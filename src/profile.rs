@@ -0,0 +1,190 @@
+//! Instruction and function-level profiler, enabled with --profile
+//!
+//! Time of every instruction is measured separately, garbage collection between instructions
+//! is measured on its own. Folded stacks are weighted by nanoseconds and can be passed
+//! to flamegraph tools.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    mem::{discriminant, Discriminant},
+    time::{Duration, Instant},
+};
+
+use runtime::runtime_types::{Context, Instructions};
+
+use crate::debugger;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub count: usize,
+    pub time: Duration,
+}
+
+impl Stat {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+#[derive(Default)]
+pub struct Profile {
+    /// per instruction kind, with its name
+    pub opcodes: HashMap<Discriminant<Instructions>, (String, Stat)>,
+    /// per code index
+    pub instructions: Vec<Stat>,
+    /// per function, None is code outside of any function
+    pub funs: HashMap<Option<usize>, Stat>,
+    /// per native function (library, function id)
+    pub natives: HashMap<(usize, usize), Stat>,
+    /// sweeps between instructions and Sweep instructions
+    pub gc: Stat,
+    /// functions on call stack, outermost first
+    pub stacks: HashMap<Vec<Option<usize>>, Duration>,
+    pub total: Duration,
+}
+
+/// runs program to the end, measuring every instruction
+pub fn run(ctx: &mut Context) -> Profile {
+    let mut profile = Profile {
+        instructions: vec![Stat::default(); ctx.code.data.len()],
+        ..Default::default()
+    };
+    let start = Instant::now();
+    loop {
+        let code_ptr = ctx.code.ptr;
+        let instr = ctx.code.data[code_ptr];
+        let mut stack: Vec<Option<usize>> = ctx.memory.stack.call_stack[1..=ctx.memory.stack.ptr]
+            .iter()
+            .filter(|frame| frame.call)
+            .map(|frame| ctx.memory.fun_at(frame.code_ptr))
+            .collect();
+        let fun = ctx.memory.fun_at(code_ptr);
+        stack.push(fun);
+
+        let instr_start = Instant::now();
+        let running = ctx.read_line();
+        let time = instr_start.elapsed();
+
+        profile
+            .opcodes
+            .entry(discriminant(&instr))
+            .or_insert_with(|| (kind(&instr), Stat::default()))
+            .1
+            .add(time);
+        profile.instructions[code_ptr].add(time);
+        profile.funs.entry(fun).or_default().add(time);
+        *profile.stacks.entry(stack).or_default() += time;
+        match instr {
+            Instructions::Cal(lib, fun_id) => profile.natives.entry((lib, fun_id)).or_default().add(time),
+            Instructions::Sweep | Instructions::SweepUnoptimized => profile.gc.add(time),
+            _ => {}
+        }
+
+        // same as Context::run
+        let gc_start = Instant::now();
+        ctx.memory.gc_sweep_unoptimized();
+        profile.gc.add(gc_start.elapsed());
+        if !running {
+            break;
+        }
+    }
+    profile.total = start.elapsed();
+    profile
+}
+
+/// variant name of instruction
+fn kind(instr: &Instructions) -> String {
+    let str = format!("{instr:?}");
+    match str.split_once('(') {
+        Some((kind, _)) => kind.to_string(),
+        None => str,
+    }
+}
+
+fn fun_name(ctx: &Context, fun: Option<usize>) -> &str {
+    match fun {
+        Some(fun) => ctx.memory.fun_table[fun].name.as_str(),
+        None => "<main>",
+    }
+}
+
+impl Profile {
+    /// text report with every table sorted by time
+    pub fn report(&self, ctx: &Context) -> String {
+        let mut res = String::new();
+        let total = self.total.as_nanos().max(1) as f64;
+        let table = |res: &mut String, title: &str, mut rows: Vec<(String, Stat)>, limit: usize| {
+            rows.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(b.1.count.cmp(&a.1.count)));
+            writeln!(res, "\n{title}").unwrap();
+            writeln!(res, "{:>12} {:>12} {:>7}  name", "count", "time (us)", "%").unwrap();
+            for (name, stat) in rows.iter().take(limit) {
+                writeln!(
+                    res,
+                    "{:>12} {:>12} {:>6.2}%  {name}",
+                    stat.count,
+                    stat.time.as_micros(),
+                    stat.time.as_nanos() as f64 / total * 100.0
+                )
+                .unwrap();
+            }
+            if rows.len() > limit {
+                writeln!(res, "{:>12} more", rows.len() - limit).unwrap();
+            }
+        };
+        writeln!(res, "Profile").unwrap();
+        writeln!(res, "Total time: {} us", self.total.as_micros()).unwrap();
+        writeln!(
+            res,
+            "GC: {} sweeps, {} us",
+            self.gc.count,
+            self.gc.time.as_micros()
+        )
+        .unwrap();
+        let funs = self
+            .funs
+            .iter()
+            .map(|(fun, stat)| (fun_name(ctx, *fun).to_string(), *stat))
+            .collect();
+        table(&mut res, "Functions (self time)", funs, usize::MAX);
+        let opcodes = self.opcodes.values().cloned().collect();
+        table(&mut res, "Instructions by kind", opcodes, usize::MAX);
+        let instructions = self
+            .instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, stat)| stat.count > 0)
+            .map(|(ptr, stat)| {
+                let name = format!("{ptr}: {:?} in {}", ctx.code.data[ptr], debugger::fun_name(ctx, ptr));
+                (name, *stat)
+            })
+            .collect();
+        table(&mut res, "Instructions by index", instructions, 20);
+        if !self.natives.is_empty() {
+            let natives = self
+                .natives
+                .iter()
+                .map(|((lib, fun_id), stat)| (format!("{}::{fun_id}", ctx.libs[*lib].name()), *stat))
+                .collect();
+            table(&mut res, "Native functions", natives, usize::MAX);
+        }
+        res
+    }
+
+    /// one line per unique call stack: "main;foo;bar nanoseconds"
+    pub fn folded(&self, ctx: &Context) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, time)| {
+                let names: Vec<&str> = stack.iter().map(|fun| fun_name(ctx, *fun)).collect();
+                format!("{} {}", names.join(";"), time.as_nanos())
+            })
+            .collect();
+        lines.sort();
+        let mut res = lines.join("\n");
+        res.push('\n');
+        res
+    }
+}
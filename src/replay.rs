@@ -0,0 +1,536 @@
+//! Record and replay of native calls, enabled with --record <file> and --replay <file>
+//!
+//! Recording wraps every library and logs result of each Cal together with memory it changed
+//! through PublicData. Replay replaces libraries with ones that return logged results and apply
+//! logged changes, so the program runs the same way without loading the real libraries.
+//!
+//! Only memory a native call can reach is compared: registers, stack values, objects and strings
+//! pointed to by registers, memory allocated by the call and garbage slots it reused.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    rc::Rc,
+};
+
+use runtime::{
+    lib::{Library, RegisterData},
+    runtime_error::ErrTypes,
+    runtime_types::{ExitCodes, Libs, PointerTypes, PublicData, Types, REGISTER_SIZE},
+};
+
+use crate::{
+    checkpoint::{
+        err_into_string, exit_code_into_string, read_err, read_exit_code, read_usizes,
+        usizes_into_string,
+    },
+    stringify::{
+        b256str, bytes_into_value, push_chars, push_str, read_char, read_number, read_str,
+        value_into_byte,
+    },
+};
+
+pub const MAGIC_NUMBER: &str = "RUDAREC";
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// change of memory made by native call
+#[derive(Debug, Clone)]
+pub enum Mutation {
+    Register(usize, Types),
+    /// new length of stack, Null fills new space
+    StackLen(usize),
+    Stack(usize, Types),
+    /// new length of heap, empty objects fill new space
+    HeapLen(usize),
+    Object(usize, Vec<Types>),
+    HeapGarbage(Vec<usize>),
+    /// new length of string pool, empty strings fill new space
+    StringsLen(usize),
+    String(usize, Vec<char>),
+    StringGarbage(Vec<usize>),
+    CodePtr(usize),
+    BreakCode(Option<usize>),
+    ExitCode(ExitCodes),
+}
+
+#[derive(Debug, Clone)]
+pub struct CallRecord {
+    /// index of library in Context.libs
+    pub lib: usize,
+    pub fun: usize,
+    pub result: Result<Types, ErrTypes>,
+    pub mutations: Vec<Mutation>,
+}
+
+/// log shared by all recording libraries of a context
+#[derive(Default)]
+pub struct Recording {
+    /// names of libraries in order of Context.libs
+    pub libs: Vec<String>,
+    pub calls: Vec<CallRecord>,
+}
+
+fn encode(value: &Types) -> String {
+    let mut str = String::new();
+    value_into_byte(*value, &mut str);
+    str
+}
+
+fn same(a: &[Types], b: &[Types]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| encode(a) == encode(b))
+}
+
+/// state of memory reachable by native call before it runs
+struct Snapshot {
+    registers: [Types; REGISTER_SIZE],
+    /// values pointed to by registers
+    stack: BTreeMap<usize, Types>,
+    heap: BTreeMap<usize, Vec<Types>>,
+    strings: BTreeMap<usize, Vec<char>>,
+    stack_len: usize,
+    heap_len: usize,
+    strings_len: usize,
+    heap_garbage: Vec<usize>,
+    string_garbage: Vec<usize>,
+    code_ptr: usize,
+    break_code: Option<usize>,
+    exit_code: String,
+}
+
+impl Snapshot {
+    fn new(mem: &PublicData) -> Self {
+        let memory = &mem.memory;
+        let mut stack = BTreeMap::new();
+        let mut heap = BTreeMap::new();
+        let mut strings = BTreeMap::new();
+        for value in memory.registers.iter() {
+            match *value {
+                Types::Pointer(idx, PointerTypes::Stack) => {
+                    if let Some(value) = memory.stack.data.get(idx) {
+                        stack.insert(idx, *value);
+                    }
+                }
+                Types::Pointer(idx, PointerTypes::Object | PointerTypes::Heap(_)) => {
+                    if let Some(obj) = memory.heap.data.get(idx) {
+                        heap.insert(idx, obj.clone());
+                    }
+                }
+                Types::Pointer(idx, PointerTypes::String) => {
+                    if let Some(str) = memory.strings.pool.get(idx) {
+                        strings.insert(idx, str.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self {
+            registers: memory.registers,
+            stack,
+            heap,
+            strings,
+            stack_len: memory.stack.data.len(),
+            heap_len: memory.heap.data.len(),
+            strings_len: memory.strings.pool.len(),
+            heap_garbage: memory.heap.garbage.clone(),
+            string_garbage: memory.strings.garbage.clone(),
+            code_ptr: mem.code.ptr,
+            break_code: *mem.break_code,
+            exit_code: format!("{:?}", mem.exit_code),
+        }
+    }
+
+    /// changes made since snapshot
+    fn diff(&self, mem: &PublicData) -> Vec<Mutation> {
+        let memory = &mem.memory;
+        let mut res = Vec::new();
+        for (reg, (old, new)) in self.registers.iter().zip(memory.registers.iter()).enumerate() {
+            if encode(old) != encode(new) {
+                res.push(Mutation::Register(reg, *new));
+            }
+        }
+        if self.stack_len != memory.stack.data.len() {
+            res.push(Mutation::StackLen(memory.stack.data.len()));
+        }
+        for idx in touched(self.stack.keys(), self.stack_len, &memory.stack.data, &[], &[]) {
+            let new = memory.stack.data[idx];
+            if self.stack.get(&idx).is_none_or(|old| encode(old) != encode(&new)) {
+                res.push(Mutation::Stack(idx, new));
+            }
+        }
+        if self.heap_len != memory.heap.data.len() {
+            res.push(Mutation::HeapLen(memory.heap.data.len()));
+        }
+        let heap = touched(
+            self.heap.keys(),
+            self.heap_len,
+            &memory.heap.data,
+            &self.heap_garbage,
+            &memory.heap.garbage,
+        );
+        for idx in heap {
+            let new = &memory.heap.data[idx];
+            if self.heap.get(&idx).is_none_or(|old| !same(old, new)) {
+                res.push(Mutation::Object(idx, new.clone()));
+            }
+        }
+        if self.heap_garbage != memory.heap.garbage {
+            res.push(Mutation::HeapGarbage(memory.heap.garbage.clone()));
+        }
+        if self.strings_len != memory.strings.pool.len() {
+            res.push(Mutation::StringsLen(memory.strings.pool.len()));
+        }
+        let strings = touched(
+            self.strings.keys(),
+            self.strings_len,
+            &memory.strings.pool,
+            &self.string_garbage,
+            &memory.strings.garbage,
+        );
+        for idx in strings {
+            let new = &memory.strings.pool[idx];
+            if self.strings.get(&idx) != Some(new) {
+                res.push(Mutation::String(idx, new.clone()));
+            }
+        }
+        if self.string_garbage != memory.strings.garbage {
+            res.push(Mutation::StringGarbage(memory.strings.garbage.clone()));
+        }
+        if self.code_ptr != mem.code.ptr {
+            res.push(Mutation::CodePtr(mem.code.ptr));
+        }
+        if self.break_code != *mem.break_code {
+            res.push(Mutation::BreakCode(*mem.break_code));
+        }
+        if self.exit_code != format!("{:?}", mem.exit_code) {
+            res.push(Mutation::ExitCode(mem.exit_code.clone()));
+        }
+        res
+    }
+}
+
+/// indices that may have changed during native call: values pointed to by registers,
+/// space added by the call and garbage slots it took for new values
+///
+/// reused slots have no value in the snapshot, so they are always recorded
+fn touched<'a, T>(
+    pointed: impl Iterator<Item = &'a usize>,
+    old_len: usize,
+    data: &[T],
+    old_garbage: &[usize],
+    garbage: &[usize],
+) -> BTreeSet<usize> {
+    let garbage: BTreeSet<usize> = garbage.iter().copied().collect();
+    pointed
+        .copied()
+        .chain(old_len..data.len())
+        .chain(old_garbage.iter().copied().filter(|idx| !garbage.contains(idx)))
+        .filter(|idx| *idx < data.len())
+        .collect()
+}
+
+fn apply(mutation: &Mutation, mem: &mut PublicData) {
+    let memory = &mut mem.memory;
+    match mutation {
+        Mutation::Register(reg, value) => memory.registers[*reg] = *value,
+        Mutation::StackLen(len) => memory.stack.data.resize(*len, Types::Null),
+        Mutation::Stack(idx, value) => memory.stack.data[*idx] = *value,
+        Mutation::HeapLen(len) => memory.heap.data.resize(*len, Vec::new()),
+        Mutation::Object(idx, obj) => memory.heap.data[*idx] = obj.clone(),
+        Mutation::HeapGarbage(garbage) => memory.heap.garbage = garbage.clone(),
+        Mutation::StringsLen(len) => memory.strings.pool.resize(*len, Vec::new()),
+        Mutation::String(idx, str) => memory.strings.pool[*idx] = str.clone(),
+        Mutation::StringGarbage(garbage) => memory.strings.garbage = garbage.clone(),
+        Mutation::CodePtr(ptr) => mem.code.ptr = *ptr,
+        Mutation::BreakCode(code) => *mem.break_code = *code,
+        Mutation::ExitCode(code) => *mem.exit_code = code.clone(),
+    }
+}
+
+/// passes calls to the real library and logs them
+pub struct RecordingLibrary {
+    inner: Box<dyn Library>,
+    /// index in Context.libs
+    lib: usize,
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl Library for RecordingLibrary {
+    fn call(&mut self, id: usize, mem: PublicData) -> Result<Types, ErrTypes> {
+        let snapshot = Snapshot::new(&mem);
+        let result = self.inner.call(
+            id,
+            PublicData {
+                memory: &mut *mem.memory,
+                code: &mut *mem.code,
+                break_code: &mut *mem.break_code,
+                exit_code: &mut *mem.exit_code,
+            },
+        );
+        let mutations = snapshot.diff(&mem);
+        self.recording.borrow_mut().calls.push(CallRecord {
+            lib: self.lib,
+            fun: id,
+            result: result.clone(),
+            mutations,
+        });
+        result
+    }
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+    fn register(&self) -> RegisterData {
+        self.inner.register()
+    }
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        self.inner.save_state()
+    }
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.inner.restore_state(state)
+    }
+}
+
+/// wraps every library so its calls are logged to the returned recording
+pub fn record(libs: Libs) -> (Libs, Rc<RefCell<Recording>>) {
+    let recording = Rc::new(RefCell::new(Recording {
+        libs: libs.iter().map(|lib| lib.name()).collect(),
+        calls: Vec::new(),
+    }));
+    let libs = libs
+        .into_iter()
+        .enumerate()
+        .map(|(lib, inner)| {
+            Box::new(RecordingLibrary {
+                inner,
+                lib,
+                recording: recording.clone(),
+            }) as Box<dyn Library>
+        })
+        .collect();
+    (libs, recording)
+}
+
+/// returns logged results instead of calling the real library
+pub struct ReplayLibrary {
+    name: String,
+    /// index in Context.libs
+    lib: usize,
+    calls: Rc<RefCell<VecDeque<CallRecord>>>,
+}
+
+impl Library for ReplayLibrary {
+    fn call(&mut self, id: usize, mut mem: PublicData) -> Result<Types, ErrTypes> {
+        let record = match self.calls.borrow_mut().pop_front() {
+            Some(record) => record,
+            None => {
+                return Err(ErrTypes::Message(format!(
+                    "Replay diverged: Cal({}, {id}) was not recorded",
+                    self.lib
+                )))
+            }
+        };
+        if (record.lib, record.fun) != (self.lib, id) {
+            return Err(ErrTypes::Message(format!(
+                "Replay diverged: expected Cal({}, {}), found Cal({}, {id})",
+                record.lib, record.fun, self.lib
+            )));
+        }
+        for mutation in record.mutations.iter() {
+            apply(mutation, &mut mem);
+        }
+        record.result
+    }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn register(&self) -> RegisterData {
+        RegisterData::new()
+    }
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        Err("position in replay log is not saved".to_string())
+    }
+}
+
+/// libraries that replay recording in place of the recorded ones
+pub fn replay(recording: Recording) -> Libs {
+    let calls = Rc::new(RefCell::new(VecDeque::from(recording.calls)));
+    recording
+        .libs
+        .into_iter()
+        .enumerate()
+        .map(|(lib, name)| {
+            Box::new(ReplayLibrary {
+                name,
+                lib,
+                calls: calls.clone(),
+            }) as Box<dyn Library>
+        })
+        .collect()
+}
+
+fn mutation_into_string(mutation: &Mutation, str: &mut String) {
+    match mutation {
+        Mutation::Register(reg, value) => {
+            str.push(0 as char);
+            str.push_str(&b256str(*reg, 1));
+            value_into_byte(*value, str);
+        }
+        Mutation::StackLen(len) => {
+            str.push(1 as char);
+            str.push_str(&b256str(*len, 8));
+        }
+        Mutation::Stack(idx, value) => {
+            str.push(2 as char);
+            str.push_str(&b256str(*idx, 8));
+            value_into_byte(*value, str);
+        }
+        Mutation::HeapLen(len) => {
+            str.push(3 as char);
+            str.push_str(&b256str(*len, 8));
+        }
+        Mutation::Object(idx, obj) => {
+            str.push(4 as char);
+            str.push_str(&b256str(*idx, 8));
+            str.push_str(&b256str(obj.len(), 8));
+            for value in obj.iter() {
+                value_into_byte(*value, str);
+            }
+        }
+        Mutation::HeapGarbage(garbage) => {
+            str.push(5 as char);
+            usizes_into_string(garbage, str);
+        }
+        Mutation::StringsLen(len) => {
+            str.push(6 as char);
+            str.push_str(&b256str(*len, 8));
+        }
+        Mutation::String(idx, chars) => {
+            str.push(7 as char);
+            str.push_str(&b256str(*idx, 8));
+            push_chars(chars, str);
+        }
+        Mutation::StringGarbage(garbage) => {
+            str.push(8 as char);
+            usizes_into_string(garbage, str);
+        }
+        Mutation::CodePtr(ptr) => {
+            str.push(9 as char);
+            str.push_str(&b256str(*ptr, 8));
+        }
+        Mutation::BreakCode(code) => {
+            str.push(10 as char);
+            match code {
+                Some(code) => {
+                    str.push(1 as char);
+                    str.push_str(&b256str(*code, 8));
+                }
+                None => str.push(0 as char),
+            }
+        }
+        Mutation::ExitCode(code) => {
+            str.push(11 as char);
+            exit_code_into_string(code, str);
+        }
+    }
+}
+
+fn read_mutation(chars: &mut Chars) -> Result<Mutation, String> {
+    Ok(match read_number(chars, 1)? {
+        0 => Mutation::Register(read_number(chars, 1)?, bytes_into_value(chars)?),
+        1 => Mutation::StackLen(read_number(chars, 8)?),
+        2 => Mutation::Stack(read_number(chars, 8)?, bytes_into_value(chars)?),
+        3 => Mutation::HeapLen(read_number(chars, 8)?),
+        4 => {
+            let idx = read_number(chars, 8)?;
+            let len = read_number(chars, 8)?;
+            Mutation::Object(
+                idx,
+                (0..len)
+                    .map(|_| bytes_into_value(chars))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        5 => Mutation::HeapGarbage(read_usizes(chars)?),
+        6 => Mutation::StringsLen(read_number(chars, 8)?),
+        7 => {
+            let idx = read_number(chars, 8)?;
+            let len = read_number(chars, 8)?;
+            Mutation::String(
+                idx,
+                (0..len)
+                    .map(|_| read_char(chars))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        8 => Mutation::StringGarbage(read_usizes(chars)?),
+        9 => Mutation::CodePtr(read_number(chars, 8)?),
+        10 => Mutation::BreakCode(match read_number(chars, 1)? {
+            0 => None,
+            _ => Some(read_number(chars, 8)?),
+        }),
+        11 => Mutation::ExitCode(read_exit_code(chars)?),
+        _ => return Err("Invalid mutation".to_string()),
+    })
+}
+
+impl Recording {
+    pub fn stringify(&self) -> String {
+        let mut res = String::from(MAGIC_NUMBER);
+        res.push_str(&b256str(self.libs.len(), 8));
+        for name in self.libs.iter() {
+            push_str(name, &mut res);
+        }
+        res.push_str(&b256str(self.calls.len(), 8));
+        for call in self.calls.iter() {
+            res.push_str(&b256str(call.lib, 8));
+            res.push_str(&b256str(call.fun, 8));
+            match &call.result {
+                Ok(value) => {
+                    res.push(0 as char);
+                    value_into_byte(*value, &mut res);
+                }
+                Err(err) => {
+                    res.push(1 as char);
+                    err_into_string(err, &mut res);
+                }
+            }
+            res.push_str(&b256str(call.mutations.len(), 8));
+            for mutation in call.mutations.iter() {
+                mutation_into_string(mutation, &mut res);
+            }
+        }
+        res
+    }
+
+    pub fn parse(str: &str) -> Result<Recording, String> {
+        let str = str
+            .strip_prefix(MAGIC_NUMBER)
+            .ok_or("The file you are trying to load is not a valid Ruda recording")?;
+        let chars = &mut str.chars().peekable();
+        let len = read_number(chars, 8)?;
+        let libs = (0..len)
+            .map(|_| read_str(chars))
+            .collect::<Result<_, _>>()?;
+        let len = read_number(chars, 8)?;
+        let mut calls = Vec::new();
+        for _ in 0..len {
+            let lib = read_number(chars, 8)?;
+            let fun = read_number(chars, 8)?;
+            let result = match read_number(chars, 1)? {
+                0 => Ok(bytes_into_value(chars)?),
+                _ => Err(read_err(chars)?),
+            };
+            let len = read_number(chars, 8)?;
+            let mutations = (0..len)
+                .map(|_| read_mutation(chars))
+                .collect::<Result<_, _>>()?;
+            calls.push(CallRecord {
+                lib,
+                fun,
+                result,
+                mutations,
+            });
+        }
+        Ok(Recording { libs, calls })
+    }
+}
@@ -0,0 +1,232 @@
+//! Machine-readable post-run report (--report-format json) and heap graph export (--heap-dot <file>)
+//!
+//! Values are written in Debug format, same as in traces. Freed heap objects and strings are left out.
+
+use std::{collections::HashSet, fmt::Write, time::Duration};
+
+use runtime::{
+    runtime_error::{error_name, RUNTIME_ERR_ID},
+    runtime_types::{Context, ExitCodes, PointerTypes, Types, EXCEPTION_REG},
+};
+
+use crate::json::Json;
+
+fn values_json(values: &[Types]) -> Json {
+    values
+        .iter()
+        .map(|value| format!("{value:?}").into())
+        .collect::<Vec<Json>>()
+        .into()
+}
+
+fn type_name(ctx: &Context, obj: &[Types]) -> Option<String> {
+    match obj.first() {
+        Some(Types::NonPrimitive(id)) if *id >= RUNTIME_ERR_ID => error_name(id - RUNTIME_ERR_ID).map(str::to_string),
+        Some(Types::NonPrimitive(id)) => ctx.memory.non_primitives.get(*id).map(|np| np.name.clone()),
+        _ => None,
+    }
+}
+
+fn exit_json(ctx: &Context) -> Json {
+    let (kind, code, message) = match &ctx.exit_code {
+        ExitCodes::End => ("end", 0, None),
+        ExitCodes::Break(_) => ("break", 0, None),
+        ExitCodes::Exception => (
+            "exception",
+            1,
+            Some(format!("{:+}", ctx.memory.registers[EXCEPTION_REG])),
+        ),
+        ExitCodes::Internal(err) => ("internal", 1, Some(err.message())),
+    };
+    Json::object(vec![
+        ("kind", kind.into()),
+        ("code", code.into()),
+        ("message", message.map_or(Json::Null, Json::from)),
+    ])
+}
+
+/// live stack frames, outermost first
+fn frames_json(ctx: &Context) -> Json {
+    let stack = &ctx.memory.stack;
+    let mut frames = Vec::new();
+    for idx in 0..=stack.ptr {
+        let start = match idx {
+            0 => 0,
+            idx => stack.call_stack[idx - 1].end,
+        };
+        let end = match idx == stack.ptr {
+            true => ctx.stack_end(),
+            false => stack.call_stack[idx].end,
+        };
+        // code pointer of the frame is stored in the frame above it
+        let code_ptr = match idx == stack.ptr {
+            true => ctx.code.ptr,
+            false => stack.call_stack[idx + 1].code_ptr,
+        };
+        let fun = match ctx.memory.fun_at(code_ptr) {
+            Some(fun) => ctx.memory.fun_table[fun].name.clone().into(),
+            None => Json::Null,
+        };
+        let values = stack.data.get(start..end.min(stack.data.len())).unwrap_or_default();
+        frames.push(Json::object(vec![
+            ("frame", idx.into()),
+            ("fun", fun),
+            ("code_ptr", code_ptr.into()),
+            ("start", start.into()),
+            ("end", end.into()),
+            ("values", values_json(values)),
+        ]));
+    }
+    frames.into()
+}
+
+/// whole report as one JSON object, run_time is None when it was not measured
+pub fn json(ctx: &Context, run_time: Option<Duration>) -> Json {
+    let registers = ctx
+        .memory
+        .registers
+        .iter()
+        .enumerate()
+        .map(|(reg, value)| {
+            Json::object(vec![("reg", reg.into()), ("value", format!("{value:?}").into())])
+        })
+        .collect::<Vec<_>>();
+    let garbage: HashSet<usize> = ctx.memory.heap.garbage.iter().copied().collect();
+    let heap = ctx
+        .memory
+        .heap
+        .data
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !garbage.contains(idx))
+        .map(|(idx, obj)| {
+            Json::object(vec![
+                ("idx", idx.into()),
+                ("type", type_name(ctx, obj).map_or(Json::Null, Json::from)),
+                ("values", values_json(obj)),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let garbage: HashSet<usize> = ctx.memory.strings.garbage.iter().copied().collect();
+    let strings = ctx
+        .memory
+        .strings
+        .pool
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !garbage.contains(idx))
+        .map(|(idx, str)| {
+            Json::object(vec![
+                ("idx", idx.into()),
+                ("value", str.iter().collect::<String>().into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let gc = &ctx.memory.gc;
+    Json::object(vec![
+        ("exit", exit_json(ctx)),
+        (
+            "run_time_us",
+            run_time.map_or(Json::Null, |time| (time.as_micros() as usize).into()),
+        ),
+        ("registers", registers.into()),
+        ("frames", frames_json(ctx)),
+        ("heap", heap.into()),
+        ("strings", strings.into()),
+        (
+            "gc",
+            Json::object(vec![
+                ("sweeps", gc.sweeps_count.into()),
+                ("memory_swept", gc.memory_swept.into()),
+                ("disabled", gc.disabled.into()),
+                ("sweep_threshold", gc.sweep_threshold.into()),
+            ]),
+        ),
+    ])
+}
+
+/// escapes DOT label, record labels also need their field separators escaped
+fn escape(str: &str, record: bool) -> String {
+    let mut res = String::new();
+    for char in str.chars() {
+        if matches!(char, '"' | '\\') || record && matches!(char, '{' | '}' | '|' | '<' | '>') {
+            res.push('\\');
+        }
+        match char {
+            '\n' => res.push_str("\\n"),
+            char => res.push(char),
+        }
+    }
+    res
+}
+
+/// live heap objects and strings as a Graphviz digraph,
+/// registers and stack are root nodes with edges to objects they point to
+pub fn dot(ctx: &Context) -> String {
+    let mem = &ctx.memory;
+    let heap_garbage: HashSet<usize> = mem.heap.garbage.iter().copied().collect();
+    let string_garbage: HashSet<usize> = mem.strings.garbage.iter().copied().collect();
+    let mut res = String::new();
+    writeln!(res, "digraph heap {{").unwrap();
+    writeln!(res, "    node [shape=record];").unwrap();
+    // field of node that points to value
+    let edge = |res: &mut String, from: String, value: &Types| {
+        let to = match value {
+            Types::Pointer(idx, PointerTypes::Object) | Types::Closure(_, idx) if !heap_garbage.contains(idx) => {
+                format!("obj{idx}")
+            }
+            // pointer to a field, edge ends at the field
+            Types::Pointer(idx, PointerTypes::Heap(field)) if !heap_garbage.contains(idx) => {
+                match mem.heap.data.get(*idx).is_some_and(|obj| *field < obj.len()) {
+                    true => format!("obj{idx}:f{field}"),
+                    false => format!("obj{idx}"),
+                }
+            }
+            Types::Pointer(idx, PointerTypes::String | PointerTypes::Char(_)) if !string_garbage.contains(idx) => {
+                format!("str{idx}")
+            }
+            _ => return,
+        };
+        writeln!(res, "    {from} -> {to};").unwrap();
+    };
+    let fields = |values: &[Types]| {
+        values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| format!("<f{idx}> {}", escape(&format!("{value:?}"), true)))
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+
+    writeln!(res, "    registers [label=\"registers|{}\"];", fields(&mem.registers)).unwrap();
+    for (reg, value) in mem.registers.iter().enumerate() {
+        edge(&mut res, format!("registers:f{reg}"), value);
+    }
+    let stack = &mem.stack.data[..ctx.stack_end().min(mem.stack.data.len())];
+    writeln!(res, "    stack [label=\"stack|{}\"];", fields(stack)).unwrap();
+    for (idx, value) in stack.iter().enumerate() {
+        edge(&mut res, format!("stack:f{idx}"), value);
+    }
+    for (idx, obj) in mem.heap.data.iter().enumerate() {
+        if heap_garbage.contains(&idx) {
+            continue;
+        }
+        let name = match type_name(ctx, obj) {
+            Some(name) => format!("{idx}: {name}"),
+            None => idx.to_string(),
+        };
+        writeln!(res, "    obj{idx} [label=\"{}|{}\"];", escape(&name, true), fields(obj)).unwrap();
+        for (field, value) in obj.iter().enumerate() {
+            edge(&mut res, format!("obj{idx}:f{field}"), value);
+        }
+    }
+    for (idx, str) in mem.strings.pool.iter().enumerate() {
+        if string_garbage.contains(&idx) {
+            continue;
+        }
+        let str = format!("{:?}", str.iter().collect::<String>());
+        writeln!(res, "    str{idx} [shape=box, label=\"{}\"];", escape(&str, false)).unwrap();
+    }
+    writeln!(res, "}}").unwrap();
+    res
+}
@@ -3,11 +3,18 @@
 use std::{collections::HashMap, path::PathBuf};
 
 use runtime::runtime_types::{
-    Context, FunSpec, Instructions, MemoryLoc, NonPrimitiveType, NonPrimitiveTypes, PointerTypes,
-    Types,
+    Context, DebugInfo, FunSpec, Instructions, LineEntry, LocalVar, MemoryLoc, NonPrimitiveType,
+    NonPrimitiveTypes, PointerTypes, RegAnnotation, Types,
 };
 
 pub const MAGIC_NUMBER: &str = "RUDA";
+/// version of the format written after the magic number
+///
+/// binaries written before the format had a version continue with the length of the code,
+/// its first byte is always 0 so they are read as version 0
+///
+/// 1: non-primitive types have pointer fields and a parent type
+pub const FORMAT_VERSION: usize = 1;
 
 #[derive(Debug)]
 /// Contains all the data that can be written to a file
@@ -18,9 +25,10 @@ pub struct Data {
     pub non_primitives: Vec<NonPrimitiveType>,
     pub fun_table: Vec<FunSpec>,
     pub shared_libs: Vec<ShLib>,
+    pub debug: Option<DebugInfo>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Describes how to find a shared library
 pub struct ShLib {
     /// The path to the library
@@ -29,7 +37,7 @@ pub struct ShLib {
     pub owns: LibOwner,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Defines where on the system the library is located
 pub enum LibOwner {
     /// The library is located in the standard library folder
@@ -44,10 +52,11 @@ pub enum LibOwner {
     Installed(String, String),
 }
 
-pub fn stringify(ctx: &Context) -> String {
+pub fn stringify(ctx: &Context, shared_libs: &[ShLib]) -> String {
     let mut res = String::new();
     // write magic number
     res.push_str(MAGIC_NUMBER);
+    res.push_str(&b256str(FORMAT_VERSION, 1));
     // write length of paragraph in 8 bytes (number of instructions)
     res.push_str(&b256str(ctx.code.data.len(), 8));
     for byte in ctx.code.data.iter() {
@@ -74,130 +83,155 @@ pub fn stringify(ctx: &Context) -> String {
         fun_spec_into_string(fun_spec, &mut res);
     }
     // write length of paragraph in 8 bytes (number of shared libraries)
-    res.push_str(&b256str(0, 8));
+    res.push_str(&b256str(shared_libs.len(), 8));
+    for lib in shared_libs.iter() {
+        lib_into_string(lib, &mut res);
+    }
+    // write optional debug section
+    match &ctx.code.debug {
+        Some(debug) => {
+            res.push(1 as char);
+            debug_into_string(debug, &mut res);
+        }
+        None => res.push(0 as char),
+    }
     res
 }
 
-pub fn parse(str: &str) -> Data {
-    let mut chars = str.chars().peekable();
+pub fn parse(str: &str) -> Result<Data, String> {
+    parse_chars(&mut str.chars().peekable())
+}
+
+/// parses binary from the start of chars and leaves the rest unread
+pub fn parse_chars(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Data, String> {
     // check magic number
     for c in MAGIC_NUMBER.chars() {
-        match chars.next() {
-            Some(c2) => {
-                if c != c2 {
-                    panic!("The file you are trying to load is not a valid Ruda binary file");
-                }
-            }
-            None => panic!("The file you are trying to load is not a valid Ruda binary file"),
+        if chars.next() != Some(c) {
+            return Err("The file you are trying to load is not a valid Ruda binary file".to_string());
         }
     }
+    let version = match chars.peek() {
+        Some('\0') => 0,
+        _ => read_number(chars, 1)?,
+    };
+    if version > FORMAT_VERSION {
+        return Err(format!(
+            "Binary format version {version} is newer than supported version {FORMAT_VERSION}"
+        ));
+    }
     let mut i = 0;
     // read length of paragraph in 8 bytes (number of instructions)
-    let len = read_number(&mut chars, 8);
-    let mut instructions = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut instructions = Vec::new();
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
-        instructions.push(str_into_byte(&mut chars));
+        instructions.push(str_into_byte(chars)?);
         i += 1;
     }
     // read length of paragraph in 8 bytes (number of values)
-    let len = read_number(&mut chars, 8);
-    let mut values = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut values = Vec::new();
     i = 0;
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
-        values.push(bytes_into_value(&mut chars));
+        values.push(bytes_into_value(chars)?);
         i += 1;
     }
     // read length of paragraph in 8 bytes (number of strings)
-    let len = read_number(&mut chars, 8);
-    let mut strings = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut strings = Vec::new();
     i = 0;
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
         // read length of string in 8 bytes
-        let len = read_number(&mut chars, 8);
-        let mut string = Vec::with_capacity(len);
+        let len = read_number(chars, 8)?;
+        let mut string = Vec::new();
         for _ in 0..len {
-            string.push(chars.next().unwrap());
+            string.push(read_char(chars)?);
         }
         strings.push(string);
         i += 1;
     }
     // read length of paragraph in 8 bytes (number of non-primitive types)
-    let len = read_number(&mut chars, 8);
-    let mut non_primitives = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut non_primitives = Vec::new();
     i = 0;
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
-        non_primitives.push(read_non_prim(&mut chars));
+        non_primitives.push(read_non_prim(chars, version)?);
         i += 1;
     }
     // read length of paragraph in 8 bytes (number of function specs)
-    let len = read_number(&mut chars, 8);
-    let mut fun_table = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut fun_table = Vec::new();
     i = 0;
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
-        fun_table.push(fun_spec_from_string(&mut chars));
+        fun_table.push(fun_spec_from_string(chars)?);
         i += 1;
     }
     // read length of paragraph in 8 bytes (number of shared libraries)
-    let len = read_number(&mut chars, 8);
-    let mut shared_libs = Vec::with_capacity(len);
+    let len = read_number(chars, 8)?;
+    let mut shared_libs = Vec::new();
     i = 0;
     while let Some(_) = chars.peek() {
         if i == len {
             break;
         }
-        let path = read_str(&mut chars);
-        let owns = match chars.next().unwrap() as u8 {
+        let path = read_str(chars)?;
+        let owns = match read_number(chars, 1)? {
             0 => LibOwner::Standard,
             1 => LibOwner::Included,
             2 => LibOwner::System,
             3 => {
-                let env_var = read_str(&mut chars);
-                let err = read_str(&mut chars);
+                let env_var = read_str(chars)?;
+                let err = read_str(chars)?;
                 LibOwner::Installed(env_var, err)
             }
-            _ => panic!("Invalid library owner flag"),
+            _ => return Err("Invalid library owner flag".to_string()),
         };
         shared_libs.push(ShLib { path, owns });
         i += 1;
     }
-    Data {
+    // read optional debug section (binaries without it end here)
+    let debug = match chars.next() {
+        Some(flag) if flag as u8 == 1 => Some(read_debug(chars)?),
+        _ => None,
+    };
+    Ok(Data {
         instructions,
         values,
         strings,
         non_primitives,
         fun_table,
         shared_libs,
-    }
+        debug,
+    })
 }
 
 use std::path::Path;
 
 impl ShLib {
-    pub fn into_real_path<'a>(&'a self, bin_loc: &'a str, vm_loc: &str) -> PathBuf {
+    pub fn into_real_path<'a>(&'a self, bin_loc: &'a str, vm_loc: &str) -> Result<PathBuf, String> {
         let mut path = Path::new(bin_loc);
-        path = path.parent().unwrap();
+        path = path.parent().unwrap_or(Path::new(""));
         let mut path = match &self.owns {
             LibOwner::Standard => Path::new(vm_loc).join("stdlib").join(&self.path),
             LibOwner::Included => path.join(&self.path),
             LibOwner::System => Path::new(&self.path).to_path_buf(),
             LibOwner::Installed(env_var, err) => {
                 // get the path from the environment variable
-                let path = std::env::var(env_var).expect(err);
+                let path = std::env::var(env_var).map_err(|_| err.clone())?;
                 // set the path to the path from the environment variable and add the library name
                 Path::new(&path).join(&self.path)
             }
@@ -214,15 +248,11 @@ impl ShLib {
                 path.with_extension("so")
             }
         };
-        path = match path.canonicalize() {
-            Ok(path) => path,
-            Err(_) => panic!("Library {:?} does not exist", path),
-        };
-        path
+        path.canonicalize()
+            .map_err(|_| format!("Library {:?} does not exist", path))
     }
 }
 
-#[allow(unused)]
 pub fn lib_into_string(lib: &ShLib, str: &mut String) {
     push_str(&lib.path, str);
     match &lib.owns {
@@ -231,8 +261,8 @@ pub fn lib_into_string(lib: &ShLib, str: &mut String) {
         LibOwner::System => str.push(2 as char),
         LibOwner::Installed(env_var, err) => {
             str.push(3 as char);
-            push_str(&env_var, str);
-            push_str(&err, str);
+            push_str(env_var, str);
+            push_str(err, str);
         }
     }
 }
@@ -263,35 +293,35 @@ pub fn fun_spec_into_string(fun_spec: &FunSpec, str: &mut String) {
     }
 }
 
-pub fn fun_spec_from_string(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> FunSpec {
-    let name = read_str(str);
-    let loc = read_number(str, 8);
-    let stack_size = match str.next().unwrap() as u8 {
+pub fn fun_spec_from_string(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<FunSpec, String> {
+    let name = read_str(str)?;
+    let loc = read_number(str, 8)?;
+    let stack_size = match read_number(str, 1)? {
         0 => None,
         1 => {
-            let size = read_number(str, 4);
-            let ptrs = read_number(str, 4);
+            let size = read_number(str, 4)?;
+            let ptrs = read_number(str, 4)?;
             Some((size, ptrs))
         }
-        _ => panic!("Invalid stack size flag"),
+        _ => return Err("Invalid stack size flag".to_string()),
     };
-    let len = read_number(str, 8);
-    let mut params = Vec::with_capacity(len);
+    let len = read_number(str, 8)?;
+    let mut params = Vec::new();
     for _ in 0..len {
-        let flag = str.next().unwrap();
-        let loc = read_number(str, 8);
-        params.push(match flag as u8 {
+        let flag = read_number(str, 1)?;
+        let loc = read_number(str, 8)?;
+        params.push(match flag {
             0 => MemoryLoc::Stack(loc),
             1 => MemoryLoc::Register(loc),
-            _ => panic!("Invalid memory location flag"),
+            _ => return Err("Invalid memory location flag".to_string()),
         });
     }
-    FunSpec {
+    Ok(FunSpec {
         name,
         loc,
         stack_size,
         params,
-    }
+    })
 }
 
 pub fn push_str(source: &str, dest: &mut String) {
@@ -308,42 +338,132 @@ pub fn push_chars(source: &Vec<char>, dest: &mut String) {
     }
 }
 
-pub fn read_non_prim(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> NonPrimitiveType {
-    let kind = read_number(str, 1);
-    let len = read_number(str, 8);
-    let name = read_str(str);
-    let pointers = read_number(str, 8);
-    let mtds_len = read_number(str, 8);
-    let mut methods = HashMap::with_capacity(mtds_len);
+pub fn read_non_prim(
+    str: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    version: usize,
+) -> Result<NonPrimitiveType, String> {
+    let kind = read_number(str, 1)?;
+    let len = read_number(str, 8)?;
+    let name = read_str(str)?;
+    let pointers = read_number(str, 8)?;
+    let pointer_fields = match version {
+        0 => None,
+        _ => match read_number(str, 1)? {
+            0 => None,
+            _ => {
+                let len = read_number(str, 8)?;
+                Some((0..len).map(|_| read_number(str, 8)).collect::<Result<_, _>>()?)
+            }
+        },
+    };
+    let parent = match version {
+        0 => None,
+        _ => match read_number(str, 1)? {
+            0 => None,
+            _ => Some(read_number(str, 8)?),
+        },
+    };
+    let mtds_len = read_number(str, 8)?;
+    let mut methods = HashMap::new();
     for _ in 0..mtds_len {
-        let trt = read_number(str, 8);
-        let mtds_len = read_number(str, 8);
-        let mut mtds = Vec::with_capacity(mtds_len);
+        let trt = read_number(str, 8)?;
+        let mtds_len = read_number(str, 8)?;
+        let mut mtds = Vec::new();
         for _ in 0..mtds_len {
-            mtds.push(read_number(str, 8));
+            mtds.push(read_number(str, 8)?);
         }
         methods.insert(trt, mtds);
     }
-    NonPrimitiveType {
+    Ok(NonPrimitiveType {
         kind: match kind {
             0 => NonPrimitiveTypes::Array,
             1 => NonPrimitiveTypes::Struct,
-            _ => panic!("Invalid non-primitive type"),
+            _ => return Err("Invalid non-primitive type".to_string()),
         },
         len,
         name,
         pointers,
+        pointer_fields,
         methods,
+        parent,
+    })
+}
+
+pub fn debug_into_string(debug: &DebugInfo, str: &mut String) {
+    str.push_str(&b256str(debug.files.len(), 8));
+    for file in debug.files.iter() {
+        push_str(file, str);
+    }
+    str.push_str(&b256str(debug.lines.len(), 8));
+    for entry in debug.lines.iter() {
+        str.push_str(&b256str(entry.ptr, 8));
+        str.push_str(&b256str(entry.file, 8));
+        str.push_str(&b256str(entry.line, 8));
+        str.push_str(&b256str(entry.column, 8));
+    }
+    str.push_str(&b256str(debug.locals.len(), 8));
+    for local in debug.locals.iter() {
+        str.push_str(&b256str(local.fun, 8));
+        str.push_str(&b256str(local.offset, 8));
+        push_str(&local.name, str);
+    }
+    str.push_str(&b256str(debug.registers.len(), 8));
+    for annotation in debug.registers.iter() {
+        str.push_str(&b256str(annotation.ptr, 8));
+        str.push_str(&b256str(annotation.reg, 1));
+        push_str(&annotation.name, str);
     }
 }
 
-fn read_str(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
-    let len = read_number(str, 8);
-    let mut res = String::with_capacity(len);
+pub fn read_debug(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<DebugInfo, String> {
+    let len = read_number(str, 8)?;
+    let mut files = Vec::new();
     for _ in 0..len {
-        res.push(str.next().unwrap());
+        files.push(read_str(str)?);
     }
-    res
+    let len = read_number(str, 8)?;
+    let mut lines = Vec::new();
+    for _ in 0..len {
+        lines.push(LineEntry {
+            ptr: read_number(str, 8)?,
+            file: read_number(str, 8)?,
+            line: read_number(str, 8)?,
+            column: read_number(str, 8)?,
+        });
+    }
+    let len = read_number(str, 8)?;
+    let mut locals = Vec::new();
+    for _ in 0..len {
+        locals.push(LocalVar {
+            fun: read_number(str, 8)?,
+            offset: read_number(str, 8)?,
+            name: read_str(str)?,
+        });
+    }
+    let len = read_number(str, 8)?;
+    let mut registers = Vec::new();
+    for _ in 0..len {
+        registers.push(RegAnnotation {
+            ptr: read_number(str, 8)?,
+            reg: read_number(str, 1)?,
+            name: read_str(str)?,
+        });
+    }
+    Ok(DebugInfo {
+        files,
+        lines,
+        locals,
+        registers,
+    })
+}
+
+pub fn read_str(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let len = read_number(str, 8)?;
+    let mut res = String::new();
+    for _ in 0..len {
+        res.push(read_char(str)?);
+    }
+    Ok(res)
 }
 
 pub fn non_prim_into_string(non_prim: &NonPrimitiveType, str: &mut String) {
@@ -355,6 +475,23 @@ pub fn non_prim_into_string(non_prim: &NonPrimitiveType, str: &mut String) {
     str.push_str(&b256str(non_prim.len, 8));
     push_str(&non_prim.name, str);
     str.push_str(&b256str(non_prim.pointers, 8));
+    match &non_prim.pointer_fields {
+        Some(fields) => {
+            str.push_str(&b256str(1, 1));
+            str.push_str(&b256str(fields.len(), 8));
+            for field in fields.iter() {
+                str.push_str(&b256str(*field, 8));
+            }
+        }
+        None => str.push_str(&b256str(0, 1)),
+    }
+    match non_prim.parent {
+        Some(parent) => {
+            str.push_str(&b256str(1, 1));
+            str.push_str(&b256str(parent, 8));
+        }
+        None => str.push_str(&b256str(0, 1)),
+    }
     let len = non_prim.methods.len();
     str.push_str(&b256str(len, 8));
     for (trt, methods) in non_prim.methods.iter() {
@@ -366,14 +503,19 @@ pub fn non_prim_into_string(non_prim: &NonPrimitiveType, str: &mut String) {
     }
 }
 
-fn read_number(str: &mut std::iter::Peekable<std::str::Chars<'_>>, len: usize) -> usize {
+/// next char, input that ends too early is an error
+pub fn read_char(str: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<char, String> {
+    str.next().ok_or_else(|| "Unexpected end of file".to_string())
+}
+
+pub fn read_number(str: &mut std::iter::Peekable<std::str::Chars<'_>>, len: usize) -> Result<usize, String> {
     let mut number = 0;
     for _ in 0..len {
         number *= 256;
-        number += str.next().unwrap() as usize;
+        number += read_char(str)? as usize;
     }
 
-    number
+    Ok(number)
 }
 
 pub fn byte_into_string(byte: Instructions, str: &mut String) {
@@ -457,118 +599,147 @@ pub fn byte_into_string(byte: Instructions, str: &mut String) {
         Instructions::ArgD(n1, n2, n3) => {
             s(54) + &b256str(n1, 1) + &b256str(n2, 1) + &b256str(n3, 1)
         }
+        Instructions::Call(n) => s(55) + &b256str(n, 4),
+        Instructions::CallDyn(n) => s(56) + &b256str(n, 1),
+        Instructions::Return => s(57),
+        Instructions::TailCall(n) => s(58) + &b256str(n, 4),
+        Instructions::TailCallDyn(n) => s(59) + &b256str(n, 1),
+        Instructions::MkCls(n1, n2) => s(60) + &b256str(n1, 1) + &b256str(n2, 1),
+        Instructions::Cpt(n1, n2) => s(61) + &b256str(n1, 4) + &b256str(n2, 1),
+        Instructions::Throw(n) => s(62) + &b256str(n, 1),
+        Instructions::Trace => s(63),
+        Instructions::Finally => s(64),
+        Instructions::EndFinally => s(65),
+        Instructions::NPSub(n1, n2) => s(66) + &b256str(n1, 1) + &b256str(n2, 4),
     };
     str.push_str(&append);
 }
 
-pub fn str_into_byte(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Instructions {
-    let code = chars.next().unwrap() as u8;
+pub fn str_into_byte(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Instructions, String> {
+    let code = read_number(chars, 1)?;
     let byte = match code {
-        0 => Instructions::Debug(read_number(chars, 1)),
-        1 => Instructions::Wr(read_number(chars, 4), read_number(chars, 1)),
-        2 => Instructions::Rd(read_number(chars, 4), read_number(chars, 1)),
-        3 => Instructions::Wrp(read_number(chars, 1)),
-        4 => Instructions::Rdp(read_number(chars, 1)),
-        5 => Instructions::Rdc(read_number(chars, 4), read_number(chars, 1)),
-        6 => Instructions::Ptr(read_number(chars, 4)),
-        7 => Instructions::Idx(read_number(chars, 4)),
-        8 => Instructions::Alc(read_number(chars, 1)),
-        9 => Instructions::RAlc(read_number(chars, 1)),
+        0 => Instructions::Debug(read_number(chars, 1)?),
+        1 => Instructions::Wr(read_number(chars, 4)?, read_number(chars, 1)?),
+        2 => Instructions::Rd(read_number(chars, 4)?, read_number(chars, 1)?),
+        3 => Instructions::Wrp(read_number(chars, 1)?),
+        4 => Instructions::Rdp(read_number(chars, 1)?),
+        5 => Instructions::Rdc(read_number(chars, 4)?, read_number(chars, 1)?),
+        6 => Instructions::Ptr(read_number(chars, 4)?),
+        7 => Instructions::Idx(read_number(chars, 4)?),
+        8 => Instructions::Alc(read_number(chars, 1)?),
+        9 => Instructions::RAlc(read_number(chars, 1)?),
         10 => Instructions::Dalc,
-        11 => Instructions::Goto(read_number(chars, 4)),
+        11 => Instructions::Goto(read_number(chars, 4)?),
         12 => Instructions::Gotop,
-        13 => Instructions::Brnc(read_number(chars, 4), read_number(chars, 4)),
+        13 => Instructions::Brnc(read_number(chars, 4)?, read_number(chars, 4)?),
         14 => Instructions::Ret,
         15 => Instructions::Ufrz,
-        16 => Instructions::Res(read_number(chars, 4), read_number(chars, 4)),
-        17 => Instructions::Swap(read_number(chars, 1), read_number(chars, 1)),
+        16 => Instructions::Res(read_number(chars, 4)?, read_number(chars, 4)?),
+        17 => Instructions::Swap(read_number(chars, 1)?, read_number(chars, 1)?),
         18 => Instructions::Add(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         19 => Instructions::Sub(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         20 => Instructions::Mul(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         21 => Instructions::Div(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         22 => Instructions::Mod(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         23 => Instructions::Equ(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         24 => Instructions::Grt(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         25 => Instructions::Less(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         26 => Instructions::And(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
         27 => Instructions::Or(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 1),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
         ),
-        28 => Instructions::Not(read_number(chars, 1), read_number(chars, 1)),
-        29 => Instructions::Cal(read_number(chars, 1), read_number(chars, 1)),
+        28 => Instructions::Not(read_number(chars, 1)?, read_number(chars, 1)?),
+        29 => Instructions::Cal(read_number(chars, 1)?, read_number(chars, 1)?),
         30 => Instructions::End,
-        31 => Instructions::Cast(read_number(chars, 1), read_number(chars, 1)),
-        32 => Instructions::Len(read_number(chars, 1)),
-        33 => Instructions::Type(read_number(chars, 1), read_number(chars, 1)),
-        34 => Instructions::Jump(read_number(chars, 4)),
+        31 => Instructions::Cast(read_number(chars, 1)?, read_number(chars, 1)?),
+        32 => Instructions::Len(read_number(chars, 1)?),
+        33 => Instructions::Type(read_number(chars, 1)?, read_number(chars, 1)?),
+        34 => Instructions::Jump(read_number(chars, 4)?),
         35 => Instructions::Frz,
         36 => Instructions::Back,
-        37 => Instructions::Move(read_number(chars, 1), read_number(chars, 1)),
+        37 => Instructions::Move(read_number(chars, 1)?, read_number(chars, 1)?),
         38 => Instructions::Sweep,
         39 => Instructions::SweepUnoptimized,
-        40 => Instructions::AlcS(read_number(chars, 4)),
-        41 => Instructions::IdxK(read_number(chars, 4)),
-        42 => Instructions::TRng(read_number(chars, 1), read_number(chars, 4)),
+        40 => Instructions::AlcS(read_number(chars, 4)?),
+        41 => Instructions::IdxK(read_number(chars, 4)?),
+        42 => Instructions::TRng(read_number(chars, 1)?, read_number(chars, 4)?),
         43 => Instructions::CpRng(
-            read_number(chars, 1),
-            read_number(chars, 1),
-            read_number(chars, 4),
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 4)?,
         ),
-        44 => Instructions::Break(read_number(chars, 1)),
+        44 => Instructions::Break(read_number(chars, 1)?),
         45 => Instructions::Mtd(
-            read_number(chars, 1),
-            read_number(chars, 4),
-            read_number(chars, 4),
+            read_number(chars, 1)?,
+            read_number(chars, 4)?,
+            read_number(chars, 4)?,
         ),
         46 => Instructions::Panic,
         47 => Instructions::Catch,
-        48 => Instructions::CatchId(read_number(chars, 4)),
+        48 => Instructions::CatchId(read_number(chars, 4)?),
         49 => Instructions::DelCatch,
-        50 => Instructions::NPType(read_number(chars, 1), read_number(chars, 4)),
+        50 => Instructions::NPType(read_number(chars, 1)?, read_number(chars, 4)?),
         51 => Instructions::StrNew,
-        52 => Instructions::IntoStr(read_number(chars, 1)),
-        53 => Instructions::ResD(read_number(chars, 1)),
-        _ => panic!("Unknown instruction"),
+        52 => Instructions::IntoStr(read_number(chars, 1)?),
+        53 => Instructions::ResD(read_number(chars, 1)?),
+        54 => Instructions::ArgD(
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+            read_number(chars, 1)?,
+        ),
+        55 => Instructions::Call(read_number(chars, 4)?),
+        56 => Instructions::CallDyn(read_number(chars, 1)?),
+        57 => Instructions::Return,
+        58 => Instructions::TailCall(read_number(chars, 4)?),
+        59 => Instructions::TailCallDyn(read_number(chars, 1)?),
+        60 => Instructions::MkCls(read_number(chars, 1)?, read_number(chars, 1)?),
+        61 => Instructions::Cpt(read_number(chars, 4)?, read_number(chars, 1)?),
+        62 => Instructions::Throw(read_number(chars, 1)?),
+        63 => Instructions::Trace,
+        64 => Instructions::Finally,
+        65 => Instructions::EndFinally,
+        66 => Instructions::NPSub(read_number(chars, 1)?, read_number(chars, 4)?),
+        _ => return Err(format!("Unknown instruction {code}")),
     };
-    byte
+    Ok(byte)
 }
 
 pub fn value_into_byte(value: Types, str: &mut String) {
@@ -583,25 +754,27 @@ pub fn value_into_byte(value: Types, str: &mut String) {
         Types::Null => s(7),
         Types::Void => s(8),
         Types::NonPrimitive(n) => s(9) + &b256str(n, 8),
+        Types::Closure(n1, n2) => s(10) + &b256str(n1, 8) + &b256str(n2, 8),
     };
     str.push_str(&res);
 }
 
-fn bytes_into_value(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Types {
-    let byte = chars.next().unwrap();
-    match byte as u8 {
-        0 => Types::Int(unsafe { std::mem::transmute::<usize, i64>(read_number(chars, 8)) }),
-        1 => Types::Float(unsafe { std::mem::transmute::<usize, f64>(read_number(chars, 8)) }),
-        2 => Types::Usize(read_number(chars, 8)),
-        3 => Types::Char(read_number(chars, 1) as u8 as char),
-        4 => Types::Bool(read_number(chars, 1) != 0),
-        5 => Types::Pointer(read_number(chars, 8), read_ptr_type(chars)),
-        6 => Types::Function(read_number(chars, 8)),
+pub fn bytes_into_value(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Types, String> {
+    let value = match read_number(chars, 1)? {
+        0 => Types::Int(unsafe { std::mem::transmute::<usize, i64>(read_number(chars, 8)?) }),
+        1 => Types::Float(unsafe { std::mem::transmute::<usize, f64>(read_number(chars, 8)?) }),
+        2 => Types::Usize(read_number(chars, 8)?),
+        3 => Types::Char(read_number(chars, 1)? as u8 as char),
+        4 => Types::Bool(read_number(chars, 1)? != 0),
+        5 => Types::Pointer(read_number(chars, 8)?, read_ptr_type(chars)?),
+        6 => Types::Function(read_number(chars, 8)?),
         7 => Types::Null,
         8 => Types::Void,
-        9 => Types::NonPrimitive(read_number(chars, 8)),
-        _ => panic!("Unknown type"),
-    }
+        9 => Types::NonPrimitive(read_number(chars, 8)?),
+        10 => Types::Closure(read_number(chars, 8)?, read_number(chars, 8)?),
+        _ => return Err("Unknown type".to_string()),
+    };
+    Ok(value)
 }
 
 fn ptr_type_into_str(t: &PointerTypes) -> String {
@@ -614,23 +787,23 @@ fn ptr_type_into_str(t: &PointerTypes) -> String {
     }
 }
 
-fn read_ptr_type(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> PointerTypes {
-    let byte = chars.next().unwrap();
-    match byte as u8 {
+fn read_ptr_type(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<PointerTypes, String> {
+    let ptr_type = match read_number(chars, 1)? {
         0 => PointerTypes::String,
         1 => PointerTypes::Object,
         2 => PointerTypes::Stack,
-        3 => PointerTypes::Char(read_number(chars, 8)),
-        4 => PointerTypes::Heap(read_number(chars, 8)),
-        _ => panic!("Unknown pointer type the program will not continue"),
-    }
+        3 => PointerTypes::Char(read_number(chars, 8)?),
+        4 => PointerTypes::Heap(read_number(chars, 8)?),
+        _ => return Err("Unknown pointer type".to_string()),
+    };
+    Ok(ptr_type)
 }
 
 pub fn into_base256(mut n: usize, fill_size: usize) -> Vec<u8> {
     if n == 0 {
         return vec![0; fill_size];
     }
-    if n > 255usize.pow(fill_size as u32) {
+    if fill_size < std::mem::size_of::<usize>() && n >> (fill_size * 8) != 0 {
         println!(
             "Important! number {} is too large to fit in {} bytes program will continue with corrupted data",
             n, fill_size
@@ -639,7 +812,7 @@ pub fn into_base256(mut n: usize, fill_size: usize) -> Vec<u8> {
 
     let mut vec = Vec::new();
     for _ in 0..fill_size {
-        vec.push((n as u8) % 255);
+        vec.push(n as u8);
         n >>= 8;
     }
     vec.reverse();
@@ -1,6 +1,12 @@
 pub mod test {
-    use std::{collections::HashMap, env, mem, path::PathBuf};
+    use std::{
+        collections::HashMap,
+        env, mem,
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
 
+    use crate::runtime::runtime_error::RUNTIME_ERR_ID;
     use crate::runtime::runtime_types::{Context, Instructions::*, Types::*, *};
     use libloading::Library;
 
@@ -246,7 +252,9 @@ pub mod test {
                         // name, age, height (includes header)
                         len: 4,
                         pointers: 1,
+                        pointer_fields: Some(vec![1]),
                         methods: HashMap::new(),
+                        parent: None,
                     },
                 ];
                 context.memory.heap.data = vec![
@@ -319,7 +327,9 @@ pub mod test {
                         len: 4,
                         // brand name
                         pointers: 1,
+                        pointer_fields: Some(vec![1]),
                         methods: HashMap::from_iter(vec![(0, vec![9, 19])]),
+                        parent: None,
                     },
                     // struct motorcycle, 3 fields, brand name, model, speed, id = 1
                     NonPrimitiveType {
@@ -329,7 +339,9 @@ pub mod test {
                         len: 4,
                         // brand name, model
                         pointers: 2,
+                        pointer_fields: Some(vec![1, 2]),
                         methods: HashMap::new(),
+                        parent: None,
                     },
                 ];
                 context.memory.fun_table = vec![
@@ -558,6 +570,922 @@ pub mod test {
                 context.code.data = vec![];
                 true
             }
+            // recursive factorial using Call and Return
+            12 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "factorial".to_string(),
+                    // n
+                    params: vec![MemoryLoc::Stack(1)],
+                    stack_size: Some((1, 0)),
+                    loc: 5,
+                }];
+                context.memory.stack.data = vec![
+                    Int(10), // n
+                    Int(1),  // step
+                ];
+                context.code.data = vec![
+                    Res(2, 0),
+                    Rdc(0, GENERAL_REG1),
+                    Call(0),
+                    Debug(RETURN_REG),
+                    End,
+                    // factorial(n)
+                    Rd(1, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    Grt(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Brnc(11, 9),
+                    // n <= 1
+                    Rdc(1, RETURN_REG),
+                    Return,
+                    // n * factorial(n - 1)
+                    Rd(1, GENERAL_REG1),
+                    Sub(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Call(0),
+                    Rd(1, GENERAL_REG1),
+                    Mul(GENERAL_REG1, RETURN_REG, RETURN_REG),
+                    Return,
+                ];
+                true
+            }
+            // tail recursive sum, goes way deeper than the call stack
+            13 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "sum".to_string(),
+                    // n, acc
+                    params: vec![MemoryLoc::Stack(2), MemoryLoc::Stack(1)],
+                    stack_size: Some((2, 0)),
+                    loc: 6,
+                }];
+                context.memory.stack.data = vec![
+                    Int(10000), // n
+                    Int(0),     // acc
+                    Int(1),     // step
+                ];
+                context.code.data = vec![
+                    Res(3, 0),
+                    Rdc(0, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    Call(0),
+                    Debug(RETURN_REG),
+                    End,
+                    // sum(n, acc)
+                    Rd(2, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    Grt(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Brnc(12, 10),
+                    // n == 0
+                    Rd(1, RETURN_REG),
+                    Return,
+                    // sum(n - 1, acc + n)
+                    Rd(1, GENERAL_REG2),
+                    Rd(2, GENERAL_REG1),
+                    Add(GENERAL_REG1, GENERAL_REG2, GENERAL_REG2),
+                    Rdc(2, GENERAL_REG3),
+                    Sub(GENERAL_REG1, GENERAL_REG3, GENERAL_REG1),
+                    TailCall(0),
+                ];
+                true
+            }
+            // closure adding captured value to its argument
+            14 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "add_captured".to_string(),
+                    // x
+                    params: vec![MemoryLoc::Stack(1)],
+                    stack_size: Some((1, 0)),
+                    loc: 15,
+                }];
+                context.memory.stack.data = vec![
+                    Function(0),
+                    Int(5),  // captured value
+                    Int(10), // argument
+                ];
+                context.code.data = vec![
+                    Res(3, 0),
+                    // environment
+                    AlcS(1),
+                    Move(POINTER_REG, GENERAL_REG2),
+                    IdxK(0),
+                    Rdc(1, GENERAL_REG1),
+                    Wrp(GENERAL_REG1),
+                    // closure
+                    Rdc(0, GENERAL_REG3),
+                    MkCls(GENERAL_REG3, GENERAL_REG2),
+                    // call
+                    Rdc(2, GENERAL_REG1),
+                    Move(GENERAL_REG3, CODE_PTR_REG),
+                    ResD(CODE_PTR_REG),
+                    ArgD(CODE_PTR_REG, 0, GENERAL_REG1),
+                    Gotop,
+                    Debug(RETURN_REG),
+                    End,
+                    // add_captured(x)
+                    Rd(1, GENERAL_REG1),
+                    Cpt(0, GENERAL_REG2),
+                    Add(GENERAL_REG1, GENERAL_REG2, RETURN_REG),
+                    Ret,
+                ];
+                true
+            }
+            // exception object thrown from a function
+            15 => {
+                context.memory.non_primitives = vec![
+                    NonPrimitiveType {
+                        name: "MyError".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        // code (includes header)
+                        len: 2,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: Some(1),
+                    },
+                    NonPrimitiveType {
+                        name: "Error".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        len: 1,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: None,
+                    },
+                ];
+                context.memory.fun_table = vec![FunSpec {
+                    name: "fails".to_string(),
+                    params: vec![],
+                    stack_size: Some((0, 0)),
+                    loc: 14,
+                }];
+                context.memory.stack.data = vec![
+                    NonPrimitive(0), // header of MyError
+                    Int(42),         // error code
+                ];
+                context.code.data = vec![
+                    Res(2, 0),
+                    // catches MyError as its parent Error
+                    CatchId(1),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(7, 4),
+                    // protected code
+                    Call(0),
+                    DelCatch,
+                    End,
+                    // catch block
+                    Debug(EXCEPTION_REG),
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    Trace,
+                    End,
+                    // fails()
+                    AlcS(2),
+                    Move(POINTER_REG, GENERAL_REG2),
+                    IdxK(0),
+                    Rdc(0, GENERAL_REG1),
+                    Wrp(GENERAL_REG1),
+                    Move(GENERAL_REG2, POINTER_REG),
+                    IdxK(1),
+                    Rdc(1, GENERAL_REG1),
+                    Wrp(GENERAL_REG1),
+                    Throw(GENERAL_REG2),
+                ];
+                true
+            }
+            // finally block running while exception propagates
+            16 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "cleanup".to_string(),
+                    params: vec![],
+                    stack_size: Some((0, 0)),
+                    loc: 8,
+                }];
+                context.memory.stack.data = vec![
+                    Int(1), // exception
+                ];
+                context.code.data = vec![
+                    Res(1, 0),
+                    Catch,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(6, 4),
+                    Call(0),
+                    End,
+                    // catch block
+                    Debug(EXCEPTION_REG),
+                    End,
+                    // cleanup()
+                    Finally,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(14, 11),
+                    Rdc(0, GENERAL_REG2),
+                    Throw(GENERAL_REG2),
+                    Return,
+                    // finally block
+                    Debug(GENERAL_REG1),
+                    EndFinally,
+                ];
+                true
+            }
+            // uncaught runtime error reported with source locations
+            17 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "add".to_string(),
+                    params: vec![MemoryLoc::Stack(2), MemoryLoc::Stack(1)],
+                    stack_size: Some((2, 0)),
+                    loc: 6,
+                }];
+                context.memory.stack.data = vec![
+                    Int(5),     // a
+                    Bool(true), // b
+                ];
+                context.code.data = vec![
+                    Res(0, 0),
+                    Rdc(0, 0),
+                    Rdc(1, 1),
+                    Call(0),
+                    Debug(RETURN_REG),
+                    End,
+                    // add(a, b)
+                    Rd(2, 0),
+                    Rd(1, 1),
+                    Add(0, 1, RETURN_REG),
+                    Return,
+                ];
+                context.code.debug = Some(DebugInfo {
+                    files: vec!["main.rd".to_string()],
+                    lines: vec![
+                        LineEntry { ptr: 0, file: 0, line: 5, column: 1 },
+                        LineEntry { ptr: 3, file: 0, line: 5, column: 12 },
+                        LineEntry { ptr: 6, file: 0, line: 1, column: 1 },
+                        LineEntry { ptr: 8, file: 0, line: 2, column: 12 },
+                    ],
+                    locals: vec![
+                        LocalVar { fun: 0, offset: 2, name: "a".to_string() },
+                        LocalVar { fun: 0, offset: 1, name: "b".to_string() },
+                    ],
+                    registers: vec![RegAnnotation { ptr: 8, reg: RETURN_REG, name: "a + b".to_string() }],
+                });
+                true
+            }
+            // native calls with different results on every run, same with --record and --replay
+            18 => {
+                context.set_libs(vec![Box::new(Clock)]);
+                context.code.data = vec![
+                    Cal(0, 0),
+                    Debug(RETURN_REG),
+                    Cal(0, 1),
+                    Debug(RETURN_REG),
+                    End,
+                ];
+                true
+            }
+            // linked list deeper than recursive marking could handle, last node points back to the head
+            19 => {
+                const LEN: usize = 1_000_000;
+                context.memory.heap.data = (0..LEN)
+                    .map(|i| vec![Int(i as i64), Pointer((i + 1) % LEN, PointerTypes::Object)])
+                    .collect();
+                // unreachable cycle, freed by first sweep
+                context.memory.heap.data.push(vec![Int(-1), Pointer(LEN + 1, PointerTypes::Object)]);
+                context.memory.heap.data.push(vec![Int(-2), Pointer(LEN, PointerTypes::Object)]);
+                context.memory.stack.data = vec![Pointer(0, PointerTypes::Object)];
+                context.code.data = vec![
+                    Res(1, 0),
+                    Rd(1, POINTER_REG),
+                    // value of second node
+                    IdxK(1),
+                    Rdp(POINTER_REG),
+                    IdxK(0),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                ];
+                false
+            }
+            // GC scans only fields listed in layout of the type, numeric array is not scanned at all
+            20 => {
+                const LEN: usize = 1_000_000;
+                context.memory.non_primitives = vec![
+                    NonPrimitiveType {
+                        name: "Holder".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        // samples, cached (includes header)
+                        len: 3,
+                        pointers: 1,
+                        pointer_fields: Some(vec![1]),
+                        methods: HashMap::new(),
+                        parent: None,
+                    },
+                    NonPrimitiveType {
+                        name: "Samples".to_string(),
+                        kind: NonPrimitiveTypes::Array,
+                        len: LEN + 1,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: None,
+                    },
+                ];
+                let mut samples = vec![Types::NonPrimitive(1)];
+                samples.extend((0..LEN).map(|i| Int(i as i64)));
+                context.memory.heap.data = vec![
+                    // cached is not a pointer field, object 2 is freed by first sweep
+                    vec![
+                        Types::NonPrimitive(0),
+                        Pointer(1, PointerTypes::Object),
+                        Pointer(2, PointerTypes::Object),
+                    ],
+                    samples,
+                    vec![Int(7)],
+                ];
+                context.memory.stack.data = vec![Pointer(0, PointerTypes::Object)];
+                context.code.data = vec![
+                    Res(1, 0),
+                    Rd(1, POINTER_REG),
+                    IdxK(1),
+                    Rdp(POINTER_REG),
+                    // sample 499999 (includes header)
+                    IdxK(500000),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                ];
+                false
+            }
+            // casts between numbers, chars and strings, failed casts are caught as runtime errors
+            21 => {
+                context.memory.strings.pool = vec![
+                    "  42 ".chars().collect(),
+                    "2.5".chars().collect(),
+                    "true".chars().collect(),
+                    "forty two".chars().collect(),
+                ];
+                context.memory.stack.data = vec![
+                    Int(65),
+                    Usize(0x263A),
+                    Char('a'),
+                    Int(0),
+                    Usize(0),
+                    Float(0.0),
+                    Float(66.0),
+                    Pointer(0, PointerTypes::String),
+                    Pointer(1, PointerTypes::String),
+                    Pointer(2, PointerTypes::String),
+                    Bool(false),
+                    Pointer(3, PointerTypes::String),
+                    Int(-1),
+                ];
+                context.code.data = vec![
+                    Res(13, 0),
+                    // Int, Usize and Float into Char
+                    Rdc(0, GENERAL_REG1),
+                    Rdc(2, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(1, GENERAL_REG1),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(6, GENERAL_REG1),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    // Char into Int, Usize and Float
+                    Rdc(2, GENERAL_REG1),
+                    Rdc(3, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(2, GENERAL_REG1),
+                    Rdc(4, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(2, GENERAL_REG1),
+                    Rdc(5, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    // strings parsed into Int, Float and Bool
+                    Rdc(7, GENERAL_REG1),
+                    Rdc(3, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(8, GENERAL_REG1),
+                    Rdc(5, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    Rdc(9, GENERAL_REG1),
+                    Rdc(10, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    Debug(GENERAL_REG1),
+                    // negative character code is caught as InvalidChar
+                    CatchId(RUNTIME_ERR_ID + 10),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(43, 38),
+                    Rdc(12, GENERAL_REG1),
+                    Rdc(2, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    DelCatch,
+                    End,
+                    // catch block, prints code of the error
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    // text that is not a number is caught as ParseError
+                    CatchId(RUNTIME_ERR_ID + 9),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(55, 50),
+                    Rdc(11, GENERAL_REG1),
+                    Rdc(3, GENERAL_REG2),
+                    Cast(GENERAL_REG1, GENERAL_REG2),
+                    DelCatch,
+                    End,
+                    // catch block, message of the error stays in string pool
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                ];
+                true
+            }
+            // Return from the base frame and Call of missing function are caught as runtime errors
+            22 => {
+                context.code.data = vec![
+                    CatchId(RUNTIME_ERR_ID + 11),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(5, 3),
+                    Return,
+                    End,
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    CatchId(RUNTIME_ERR_ID + 12),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(14, 12),
+                    Call(99),
+                    End,
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                ];
+                false
+            }
+            // tail call from the base frame, catch blocks of function replaced by tail call
+            23 => {
+                context.memory.fun_table = vec![
+                    FunSpec {
+                        name: "guarded".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 12,
+                    },
+                    FunSpec {
+                        name: "fails".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 19,
+                    },
+                    FunSpec {
+                        name: "answer".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 21,
+                    },
+                ];
+                context.memory.stack.data = vec![Int(1), Int(42)];
+                context.code.data = vec![
+                    // acts as a plain call
+                    TailCall(2),
+                    Debug(RETURN_REG),
+                    CatchId(RUNTIME_ERR_ID + 12),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(7, 5),
+                    Call(0),
+                    End,
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // guarded(), its catch block is gone after the tail call
+                    Catch,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(16, 15),
+                    TailCall(1),
+                    Rdc(0, GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    Return,
+                    // fails()
+                    Call(99),
+                    Return,
+                    // answer()
+                    Rdc(1, RETURN_REG),
+                    Return,
+                ];
+                false
+            }
+            // recursion 50 calls deep, overflows with --max-call-depth or --max-catch-depth below that
+            24 => {
+                context.memory.fun_table = vec![
+                    FunSpec {
+                        name: "down".to_string(),
+                        // n
+                        params: vec![MemoryLoc::Stack(1)],
+                        stack_size: Some((1, 0)),
+                        loc: 26,
+                    },
+                    FunSpec {
+                        name: "guarded_down".to_string(),
+                        // n
+                        params: vec![MemoryLoc::Stack(1)],
+                        stack_size: Some((1, 0)),
+                        loc: 36,
+                    },
+                ];
+                context.memory.stack.data = vec![Int(0), Int(1), Int(50)];
+                context.code.data = vec![
+                    Res(3, 0),
+                    // down(50)
+                    CatchId(RUNTIME_ERR_ID + 5),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(9, 4),
+                    Rdc(2, GENERAL_REG1),
+                    Call(0),
+                    Debug(GENERAL_REG1),
+                    DelCatch,
+                    Goto(13),
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    // guarded_down(50)
+                    CatchId(RUNTIME_ERR_ID + 6),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(21, 16),
+                    Rdc(2, GENERAL_REG1),
+                    Call(1),
+                    Debug(GENERAL_REG1),
+                    DelCatch,
+                    End,
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // down(n)
+                    Rd(1, GENERAL_REG1),
+                    Rdc(0, GENERAL_REG2),
+                    Grt(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Brnc(31, 30),
+                    Return,
+                    Rd(1, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    Sub(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Call(0),
+                    Return,
+                    // guarded_down(n), every call registers a catch block that never matches
+                    CatchId(RUNTIME_ERR_ID + 8),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(39, 39),
+                    Rd(1, GENERAL_REG1),
+                    Rdc(0, GENERAL_REG2),
+                    Grt(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Brnc(45, 43),
+                    DelCatch,
+                    Return,
+                    Rd(1, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    Sub(GENERAL_REG1, GENERAL_REG2, GENERAL_REG1),
+                    Call(1),
+                    DelCatch,
+                    Return,
+                ];
+                false
+            }
+            // frame of a finished closure call reused by a plain block, environment is not kept
+            25 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "add_captured".to_string(),
+                    // x
+                    params: vec![MemoryLoc::Stack(1)],
+                    stack_size: Some((1, 0)),
+                    loc: 32,
+                }];
+                context.memory.stack.data = vec![
+                    Function(0),
+                    Int(5),  // captured value
+                    Int(10), // argument
+                    Null,
+                ];
+                context.code.data = vec![
+                    Res(4, 0),
+                    // environment
+                    AlcS(1),
+                    Move(POINTER_REG, GENERAL_REG2),
+                    IdxK(0),
+                    Rdc(1, GENERAL_REG1),
+                    Wrp(GENERAL_REG1),
+                    // closure
+                    Rdc(0, GENERAL_REG3),
+                    MkCls(GENERAL_REG3, GENERAL_REG2),
+                    // call
+                    Rdc(2, GENERAL_REG1),
+                    Move(GENERAL_REG3, CODE_PTR_REG),
+                    ResD(CODE_PTR_REG),
+                    ArgD(CODE_PTR_REG, 0, GENERAL_REG1),
+                    Gotop,
+                    Debug(RETURN_REG),
+                    // nothing refers to the environment anymore
+                    Rdc(3, GENERAL_REG2),
+                    Move(GENERAL_REG2, GENERAL_REG3),
+                    Move(GENERAL_REG2, CODE_PTR_REG),
+                    Move(GENERAL_REG2, POINTER_REG),
+                    Sweep,
+                    // plain block in the frame used by the closure
+                    Res(1, 0),
+                    Sweep,
+                    // takes place of the freed environment
+                    AlcS(1),
+                    CatchId(RUNTIME_ERR_ID + 1),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(27, 25),
+                    Cpt(0, GENERAL_REG1),
+                    End,
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // add_captured(x)
+                    Rd(1, GENERAL_REG1),
+                    Cpt(0, GENERAL_REG2),
+                    Add(GENERAL_REG1, GENERAL_REG2, RETURN_REG),
+                    Ret,
+                ];
+                true
+            }
+            // runtime error inside a block of a function caught by its type in the caller
+            26 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "fails".to_string(),
+                    params: vec![],
+                    stack_size: Some((1, 0)),
+                    loc: 13,
+                }];
+                context.memory.stack.data = vec![Int(7), Bool(true)];
+                context.code.data = vec![
+                    Res(2, 0),
+                    CatchId(RUNTIME_ERR_ID),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(6, 4),
+                    Call(0),
+                    End,
+                    // code of the error, message stays in string pool
+                    Move(EXCEPTION_REG, POINTER_REG),
+                    IdxK(1),
+                    Rdp(GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    // frames of fails() are gone, local of main is read relative to its frame
+                    Rd(2, GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // fails()
+                    Res(1, 0),
+                    Rdc(0, GENERAL_REG1),
+                    Rdc(1, GENERAL_REG2),
+                    // cleared when the frame is unwound
+                    Wr(1, GENERAL_REG2),
+                    Add(GENERAL_REG1, GENERAL_REG2, GENERAL_REG3),
+                    Return,
+                ];
+                true
+            }
+            // objects referenced only from frames that returned are freed
+            27 => {
+                context.memory.fun_table = vec![FunSpec {
+                    name: "keep".to_string(),
+                    params: vec![],
+                    stack_size: Some((1, 0)),
+                    loc: 6,
+                }];
+                context.memory.stack.data = vec![Null];
+                context.code.data = vec![
+                    Res(1, 0),
+                    Call(0),
+                    SweepUnoptimized,
+                    // takes place of the object of keep()
+                    AlcS(1),
+                    Debug(POINTER_REG),
+                    End,
+                    // keep(), pointer stays on the stack after return
+                    AlcS(1),
+                    Wr(1, POINTER_REG),
+                    Rdc(0, POINTER_REG),
+                    Return,
+                ];
+                true
+            }
+            // catch block of a function that returned does not catch exceptions of the next call
+            28 => {
+                context.memory.fun_table = vec![
+                    FunSpec {
+                        name: "registers".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 11,
+                    },
+                    FunSpec {
+                        name: "throws".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 18,
+                    },
+                ];
+                context.memory.stack.data = vec![Int(1), Int(2), Int(3)];
+                context.code.data = vec![
+                    Res(3, 0),
+                    Catch,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(7, 4),
+                    Call(0),
+                    Call(1),
+                    End,
+                    Debug(EXCEPTION_REG),
+                    Rdc(1, GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // registers(), catch block is left by Return
+                    Catch,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(15, 14),
+                    Return,
+                    Rdc(2, GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    End,
+                    // throws(), same depth as registers()
+                    Rdc(0, GENERAL_REG1),
+                    Throw(GENERAL_REG1),
+                ];
+                true
+            }
+            // finally blocks left by DelCatch, by Return and by an exception caught inside of them
+            29 => {
+                context.memory.fun_table = vec![
+                    FunSpec {
+                        name: "returns".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 13,
+                    },
+                    FunSpec {
+                        name: "rethrows".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 20,
+                    },
+                ];
+                context.memory.stack.data = vec![Int(1), Int(2), Int(3), Int(4)];
+                context.code.data = vec![
+                    Res(4, 0),
+                    Finally,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(11, 4),
+                    DelCatch,
+                    // continues here after the finally block
+                    Rdc(2, GENERAL_REG1),
+                    Debug(GENERAL_REG1),
+                    Call(0),
+                    Debug(RETURN_REG),
+                    Call(1),
+                    End,
+                    // finally block
+                    Debug(GENERAL_REG1),
+                    EndFinally,
+                    // returns(), returned value survives the finally block
+                    Finally,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(18, 16),
+                    Rdc(3, RETURN_REG),
+                    Return,
+                    Debug(GENERAL_REG1),
+                    EndFinally,
+                    // rethrows()
+                    Finally,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(25, 23),
+                    Rdc(0, GENERAL_REG1),
+                    Throw(GENERAL_REG1),
+                    // finally block catches an exception of its own
+                    Catch,
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(30, 28),
+                    Rdc(1, GENERAL_REG1),
+                    Throw(GENERAL_REG1),
+                    Debug(EXCEPTION_REG),
+                    EndFinally,
+                ];
+                true
+            }
+            // tail call from a block inside of a function and from a block outside of any function
+            30 => {
+                context.memory.fun_table = vec![
+                    FunSpec {
+                        name: "outer".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 8,
+                    },
+                    FunSpec {
+                        name: "counted".to_string(),
+                        params: vec![],
+                        stack_size: Some((0, 0)),
+                        loc: 11,
+                    },
+                ];
+                context.memory.stack.data = vec![Int(5), Int(7)];
+                context.code.data = vec![
+                    Res(2, 0),
+                    // freezed by the call, restored by Return of counted()
+                    Rdc(1, GENERAL_REG2),
+                    Call(0),
+                    Debug(GENERAL_REG2),
+                    Res(0, 0),
+                    // plain call, returns into the block
+                    TailCall(1),
+                    Debug(RETURN_REG),
+                    End,
+                    // outer(), block frame is unwound and frame of outer() is reused
+                    Res(1, 0),
+                    Rdc(0, GENERAL_REG1),
+                    TailCall(1),
+                    // counted()
+                    Rdc(0, RETURN_REG),
+                    Debug(RETURN_REG),
+                    Return,
+                ];
+                true
+            }
+            // subtypes checked by NPSub and matched by catch blocks of their parent
+            31 => {
+                context.memory.non_primitives = vec![
+                    NonPrimitiveType {
+                        name: "Error".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        len: 1,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: None,
+                    },
+                    NonPrimitiveType {
+                        name: "MyError".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        len: 1,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: Some(0),
+                    },
+                    NonPrimitiveType {
+                        name: "Other".to_string(),
+                        kind: NonPrimitiveTypes::Struct,
+                        len: 1,
+                        pointers: 0,
+                        pointer_fields: Some(vec![]),
+                        methods: HashMap::new(),
+                        parent: None,
+                    },
+                ];
+                context.memory.stack.data = vec![NonPrimitive(1), NonPrimitive(0)];
+                context.code.data = vec![
+                    Res(2, 0),
+                    Rdc(0, GENERAL_REG1),
+                    NPSub(GENERAL_REG1, 0),
+                    Debug(GENERAL_REG3),
+                    NPSub(GENERAL_REG1, 2),
+                    Debug(GENERAL_REG3),
+                    // parent is not a subtype of its child
+                    Rdc(1, GENERAL_REG1),
+                    NPSub(GENERAL_REG1, 1),
+                    Debug(GENERAL_REG3),
+                    // MyError passes catch block of Other and is caught by catch block of Error
+                    CatchId(0),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(20, 12),
+                    CatchId(2),
+                    Move(CATCH_REG, GENERAL_REG1),
+                    Brnc(18, 15),
+                    Rdc(0, GENERAL_REG1),
+                    Throw(GENERAL_REG1),
+                    End,
+                    Debug(EXCEPTION_REG),
+                    End,
+                    Debug(EXCEPTION_REG),
+                    End,
+                ];
+                true
+            }
             _ => {
                 context.memory.stack.data = vec![Int(0)];
                 context.code.data = vec![End];
@@ -566,14 +1494,14 @@ pub mod test {
             }
         }
     }
-    pub fn load_lib(path: &PathBuf) -> Box<dyn runtime::lib::Library> {
-        let lib = unsafe { Library::new(path).unwrap() };
+    pub fn load_lib(path: &PathBuf) -> Result<Box<dyn runtime::lib::Library>, String> {
+        let lib = unsafe { Library::new(path) }.map_err(|err| format!("{}: {err}", path.display()))?;
         let init_fn: libloading::Symbol<fn() -> Box<dyn runtime::lib::Library>> =
-            unsafe { lib.get(b"init").unwrap() };
+            unsafe { lib.get(b"init") }.map_err(|err| format!("{}: {err}", path.display()))?;
         let lib_box = init_fn();
 
         mem::forget(lib);
-        lib_box
+        Ok(lib_box)
     }
     pub fn load_libs(libs: Vec<&str>) -> Vec<Box<dyn runtime::lib::Library>> {
         let mut result = vec![];
@@ -591,6 +1519,27 @@ pub mod test {
         drop(libs);
         result
     }
+    /// library returning current time, used to test record and replay
+    struct Clock;
+    impl runtime::lib::Library for Clock {
+        fn call(&mut self, id: usize, mem: PublicData) -> Result<Types, runtime::runtime_error::ErrTypes> {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64;
+            match id {
+                0 => Ok(Int(now)),
+                1 => Ok(Pointer(
+                    mem.memory.strings.from_string(format!("now: {now}")),
+                    PointerTypes::String,
+                )),
+                _ => Err(runtime::runtime_error::ErrTypes::MethodNotFound),
+            }
+        }
+        fn name(&self) -> String {
+            "clock".to_string()
+        }
+        fn register(&self) -> runtime::lib::RegisterData {
+            runtime::lib::RegisterData::new()
+        }
+    }
     // Returns path to standard library
     pub fn std_path(lib: &str) -> String {
         let mut std = env::var("RUDA_PATH").expect("RUDA_PATH not set, please set it to the path of the Ruda directory");
@@ -0,0 +1,457 @@
+//! Reverse execution for the debuggers, limited with --history <instructions>
+//!
+//! Every SNAPSHOT_INTERVAL instructions the mutable state of Context is copied, every executed
+//! instruction is added to the write log together with registers it changed and whether it may
+//! have written to memory. Going back restores the closest earlier snapshot and executes
+//! instructions up to the target again, so output of the program is repeated and state of
+//! libraries is not restored. Native functions are never called again: state after every Cal
+//! is copied too, and stepping over a Cal that is already in history restores that copy.
+//! The earliest snapshots are dropped when history is longer than the limit or when all
+//! snapshots together take more than SNAPSHOT_BYTES.
+
+use std::collections::VecDeque;
+
+use runtime::runtime_types::{
+    CallStack, Catch, Catches, Context, Exception, ExitCodes, Instructions, PointerTypes, Registers, Types,
+    WatchLoc,
+};
+
+use crate::debugger;
+
+/// instructions between snapshots, also the most instructions executed again when going back
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+/// most memory taken by snapshots, the latest snapshot is kept even if it is larger
+const SNAPSHOT_BYTES: usize = 256 * 1024 * 1024;
+
+/// location of written value
+#[derive(Debug, Clone, Copy)]
+pub enum Loc {
+    Register(usize),
+    Memory(WatchLoc),
+}
+
+/// executed instruction
+struct Entry {
+    code_ptr: usize,
+    /// bit per changed register
+    registers: u32,
+    /// instruction may have written to memory, allocated or freed
+    memory: bool,
+}
+
+/// state of Context before instruction `step`
+struct Snapshot {
+    step: usize,
+    code_ptr: usize,
+    registers: Registers,
+    stack: Vec<Types>,
+    stack_ptr: usize,
+    call_stack: Vec<CallStack>,
+    heap: Vec<Vec<Types>>,
+    heap_garbage: Vec<usize>,
+    strings: Vec<Vec<char>>,
+    string_garbage: Vec<usize>,
+    /// sweeps_count, memory_swept, disabled, sweep_threshold
+    gc: (usize, usize, bool, usize),
+    break_code: Option<usize>,
+    catches: Catches,
+    exit_code: ExitCodes,
+    exception: Option<Exception>,
+    /// false if program ended with the previous instruction
+    running: bool,
+    /// approximate size of copied memory
+    bytes: usize,
+}
+
+impl Snapshot {
+    fn take(ctx: &Context, step: usize) -> Self {
+        let mem = &ctx.memory;
+        let values = mem.stack.data.len() + mem.heap.data.iter().map(Vec::len).sum::<usize>();
+        let chars = mem.strings.pool.iter().map(Vec::len).sum::<usize>();
+        let usizes = mem.heap.garbage.len() + mem.strings.garbage.len();
+        let bytes = std::mem::size_of::<Self>()
+            + values * std::mem::size_of::<Types>()
+            + mem.heap.data.len() * std::mem::size_of::<Vec<Types>>()
+            + chars * std::mem::size_of::<char>()
+            + mem.strings.pool.len() * std::mem::size_of::<Vec<char>>()
+            + usizes * std::mem::size_of::<usize>()
+            + mem.stack.call_stack.len() * std::mem::size_of::<CallStack>()
+            + ctx.catches.cache.len() * std::mem::size_of::<Catch>();
+        Self {
+            step,
+            code_ptr: ctx.code.ptr,
+            registers: mem.registers,
+            stack: mem.stack.data.clone(),
+            stack_ptr: mem.stack.ptr,
+            call_stack: mem.stack.call_stack.clone(),
+            heap: mem.heap.data.clone(),
+            heap_garbage: mem.heap.garbage.clone(),
+            strings: mem.strings.pool.clone(),
+            string_garbage: mem.strings.garbage.clone(),
+            gc: (
+                mem.gc.sweeps_count,
+                mem.gc.memory_swept,
+                mem.gc.disabled,
+                mem.gc.sweep_threshold,
+            ),
+            break_code: ctx.break_code,
+            catches: ctx.catches.clone(),
+            exit_code: ctx.exit_code.clone(),
+            exception: ctx.exception.clone(),
+            running: true,
+            bytes,
+        }
+    }
+
+    fn restore(&self, ctx: &mut Context) {
+        let mem = &mut ctx.memory;
+        ctx.code.ptr = self.code_ptr;
+        mem.registers = self.registers;
+        mem.stack.data.clone_from(&self.stack);
+        mem.stack.ptr = self.stack_ptr;
+        mem.stack.call_stack.clone_from(&self.call_stack);
+        mem.heap.data.clone_from(&self.heap);
+        mem.heap.garbage.clone_from(&self.heap_garbage);
+        mem.strings.pool.clone_from(&self.strings);
+        mem.strings.garbage.clone_from(&self.string_garbage);
+        (
+            mem.gc.sweeps_count,
+            mem.gc.memory_swept,
+            mem.gc.disabled,
+            mem.gc.sweep_threshold,
+        ) = self.gc;
+        ctx.break_code = self.break_code;
+        ctx.catches = self.catches.clone();
+        ctx.exit_code = self.exit_code.clone();
+        ctx.exception = self.exception.clone();
+        ctx.watch_hits.clear();
+    }
+}
+
+/// same value of the same type
+fn same(a: &Types, b: &Types) -> bool {
+    use Types::*;
+    match (a, b) {
+        (Int(a), Int(b)) => a == b,
+        (Float(a), Float(b)) => a.to_bits() == b.to_bits(),
+        (Usize(a), Usize(b)) => a == b,
+        (Char(a), Char(b)) => a == b,
+        (Bool(a), Bool(b)) => a == b,
+        (NonPrimitive(a), NonPrimitive(b)) | (Function(a), Function(b)) => a == b,
+        (Closure(a, env_a), Closure(b, env_b)) => a == b && env_a == env_b,
+        (Null, Null) | (Void, Void) => true,
+        (Pointer(a, kind_a), Pointer(b, kind_b)) => {
+            use PointerTypes::*;
+            a == b
+                && match (kind_a, kind_b) {
+                    (Stack, Stack) | (Object, Object) | (String, String) => true,
+                    (Heap(a), Heap(b)) | (Char(a), Char(b)) => a == b,
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+/// sizes of memory pools, instruction that changes any of them allocated or freed memory
+fn pool_sizes(ctx: &Context) -> [usize; 5] {
+    let mem = &ctx.memory;
+    [
+        mem.stack.data.len(),
+        mem.heap.data.len(),
+        mem.heap.garbage.len(),
+        mem.strings.pool.len(),
+        mem.strings.garbage.len(),
+    ]
+}
+
+/// value of exception being thrown
+fn exception_value(ctx: &Context) -> Option<Types> {
+    ctx.exception.as_ref().map(|exception| exception.value)
+}
+
+pub struct History {
+    /// instructions executed since start of program, current state is before instruction `now`
+    now: usize,
+    /// most instructions kept, 0 disables history
+    limit: usize,
+    /// ordered by step, first one is the earliest state that can be restored
+    snapshots: VecDeque<Snapshot>,
+    /// entry of step snapshots[0].step + i
+    log: VecDeque<Entry>,
+    /// sum of bytes of snapshots
+    bytes: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            now: 0,
+            limit,
+            snapshots: VecDeque::new(),
+            log: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// first step that can be travelled to
+    fn start(&self) -> usize {
+        self.snapshots.front().map_or(self.now, |snapshot| snapshot.step)
+    }
+
+    fn entry(&self, step: usize) -> &Entry {
+        &self.log[step - self.start()]
+    }
+
+    /// step after the last recorded one
+    fn end(&self) -> usize {
+        self.start() + self.log.len()
+    }
+
+    /// executes one instruction and adds it to history, same as debugger::step
+    pub fn step(&mut self, ctx: &mut Context) -> bool {
+        if self.limit == 0 {
+            return debugger::step(ctx);
+        }
+        if let Some(running) = self.replay_native(ctx) {
+            return running;
+        }
+        self.forget_future();
+        let due = match self.snapshots.back() {
+            Some(last) => last.step != self.now && self.now.is_multiple_of(SNAPSHOT_INTERVAL),
+            None => true,
+        };
+        if due {
+            self.push_snapshot(Snapshot::take(ctx, self.now));
+        }
+        let code_ptr = ctx.code.ptr;
+        let registers = ctx.memory.registers;
+        let sizes = pool_sizes(ctx);
+        let stack_ptr = ctx.memory.stack.ptr;
+        let exception = exception_value(ctx);
+        // calls write arguments to the stack of the new frame
+        let writes = matches!(
+            ctx.code.data[code_ptr],
+            Instructions::Wr(..)
+                | Instructions::Wrp(..)
+                | Instructions::CpRng(..)
+                | Instructions::TRng(..)
+                | Instructions::RAlc(..)
+                | Instructions::Cal(..)
+                | Instructions::Call(..)
+                | Instructions::CallDyn(..)
+                | Instructions::TailCall(..)
+                | Instructions::TailCallDyn(..)
+                | Instructions::ArgD(..)
+        );
+        let native = matches!(ctx.code.data[code_ptr], Instructions::Cal(..));
+        let running = debugger::step(ctx);
+        let unwound = stack_ptr != ctx.memory.stack.ptr
+            || match (exception, exception_value(ctx)) {
+                (Some(old), Some(new)) => !same(&old, &new),
+                (old, new) => old.is_some() != new.is_some(),
+            };
+        let mut changed = 0;
+        for (reg, (old, new)) in registers.iter().zip(ctx.memory.registers.iter()).enumerate() {
+            if !same(old, new) {
+                changed |= 1 << reg;
+            }
+        }
+        self.log.push_back(Entry {
+            code_ptr,
+            registers: changed,
+            memory: writes || unwound || sizes != pool_sizes(ctx),
+        });
+        self.now += 1;
+        if native {
+            let mut snapshot = Snapshot::take(ctx, self.now);
+            snapshot.running = running;
+            self.push_snapshot(snapshot);
+        }
+        self.prune();
+        running
+    }
+
+    fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.bytes += snapshot.bytes;
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// drops the earliest snapshots and their entries while history does not fit in limits
+    fn prune(&mut self) {
+        while self.snapshots.len() > 1
+            && (self.now - self.snapshots[1].step >= self.limit || self.bytes > SNAPSHOT_BYTES)
+        {
+            let snapshot = self.snapshots.pop_front().unwrap();
+            self.bytes -= snapshot.bytes;
+            self.log.drain(..self.snapshots[0].step - snapshot.step);
+        }
+    }
+
+    /// removes the latest snapshot
+    fn pop_snapshot(&mut self) {
+        if let Some(snapshot) = self.snapshots.pop_back() {
+            self.bytes -= snapshot.bytes;
+        }
+    }
+
+    /// state after Cal at current step if it was already executed, the native function
+    /// is not called again; returns whether program is running as debugger::step
+    fn replay_native(&mut self, ctx: &mut Context) -> Option<bool> {
+        if self.now >= self.end() || !matches!(ctx.code.data[ctx.code.ptr], Instructions::Cal(..)) {
+            return None;
+        }
+        let snapshot = self.snapshots.iter().find(|snapshot| snapshot.step == self.now + 1)?;
+        let code_ptr = ctx.code.ptr;
+        let old = ctx.watch_values();
+        snapshot.restore(ctx);
+        ctx.report_watch_hits(code_ptr, old);
+        self.now += 1;
+        Some(snapshot.running)
+    }
+
+    /// drops entries after current step, they are recorded again when execution continues
+    fn forget_future(&mut self) {
+        let len = self.now - self.start();
+        self.log.truncate(len);
+        while self.snapshots.back().is_some_and(|snapshot| snapshot.step > self.now) {
+            self.pop_snapshot();
+        }
+    }
+
+    /// state of ctx was changed by user, steps before it are executed without the change
+    pub fn modified(&mut self, ctx: &Context) {
+        if self.limit == 0 {
+            return;
+        }
+        self.forget_future();
+        if self.snapshots.back().is_some_and(|snapshot| snapshot.step == self.now) {
+            self.pop_snapshot();
+        }
+        self.push_snapshot(Snapshot::take(ctx, self.now));
+        self.prune();
+    }
+
+    /// restores state before instruction `step`, returns false if it is not in history
+    pub fn travel_to(&mut self, ctx: &mut Context, step: usize) -> bool {
+        if step < self.start() || step > self.end() {
+            return false;
+        }
+        let snapshot = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.step <= step)
+            .unwrap();
+        snapshot.restore(ctx);
+        let from = snapshot.step;
+        // watchpoints were already reported when these instructions first ran
+        let watchpoints = std::mem::take(&mut ctx.watchpoints);
+        for step in from..step {
+            self.redo(ctx, step);
+        }
+        ctx.watchpoints = watchpoints;
+        self.now = step;
+        true
+    }
+
+    /// executes instruction of step again, Cal restores state recorded after it instead
+    fn redo(&self, ctx: &mut Context, step: usize) {
+        if matches!(ctx.code.data[ctx.code.ptr], Instructions::Cal(..)) {
+            if let Some(snapshot) = self.snapshots.iter().find(|snapshot| snapshot.step == step + 1) {
+                snapshot.restore(ctx);
+                return;
+            }
+        }
+        debugger::step(ctx);
+    }
+
+    /// latest step before current one that matches
+    fn find_back(&self, matches: impl Fn(&Entry) -> bool) -> Option<usize> {
+        (self.start()..self.now).rev().find(|step| matches(self.entry(*step)))
+    }
+
+    /// goes back by one instruction
+    pub fn step_back(&mut self, ctx: &mut Context) -> bool {
+        self.now > self.start() && self.travel_to(ctx, self.now - 1)
+    }
+
+    /// goes back to the last executed instruction at one of locations,
+    /// or to the start of history when none is found; returns whether one was found
+    pub fn run_back(&mut self, ctx: &mut Context, locs: impl Fn(usize) -> bool) -> bool {
+        match self.find_back(|entry| locs(entry.code_ptr)) {
+            Some(step) => self.travel_to(ctx, step),
+            None => {
+                self.travel_to(ctx, self.start());
+                false
+            }
+        }
+    }
+
+    /// goes back to the last executed Brnc, before it decided where to jump
+    pub fn branch_back(&mut self, ctx: &mut Context) -> bool {
+        let code = &ctx.code.data;
+        match self.find_back(|entry| matches!(code[entry.code_ptr], Instructions::Brnc(..))) {
+            Some(step) => self.travel_to(ctx, step),
+            None => false,
+        }
+    }
+
+    /// goes back to the last instruction that changed location, before it was executed
+    pub fn write_back(&mut self, ctx: &mut Context, loc: Loc) -> bool {
+        let found = match loc {
+            Loc::Register(reg) => self.find_back(|entry| entry.registers & (1 << reg) != 0),
+            Loc::Memory(loc) => self.find_memory_write(ctx, loc),
+        };
+        match found {
+            Some(step) => self.travel_to(ctx, step),
+            None => false,
+        }
+    }
+
+    /// log only knows which instructions may have written to memory, candidates are
+    /// executed again one window between snapshots at a time, latest window first
+    fn find_memory_write(&mut self, ctx: &mut Context, loc: WatchLoc) -> Option<usize> {
+        let now = self.now;
+        let mut found = None;
+        for i in (0..self.snapshots.len()).rev() {
+            let from = self.snapshots[i].step;
+            if from >= now {
+                continue;
+            }
+            let to = self.snapshots.get(i + 1).map_or(now, |next| next.step.min(now));
+            if !(from..to).any(|step| self.entry(step).memory) {
+                continue;
+            }
+            self.travel_to(ctx, from);
+            let watchpoints = std::mem::take(&mut ctx.watchpoints);
+            for step in from..to {
+                if !self.entry(step).memory {
+                    self.redo(ctx, step);
+                    continue;
+                }
+                let old = ctx.memory.watch_value(loc);
+                self.redo(ctx, step);
+                if ctx.memory.watch_value(loc) != old {
+                    found = Some(step);
+                }
+            }
+            ctx.watchpoints = watchpoints;
+            if found.is_some() {
+                break;
+            }
+        }
+        // state is wherever the search ended, go back to where it started
+        self.travel_to(ctx, now);
+        found
+    }
+
+    /// code pointer of the instruction executed before current state, None at start of history
+    pub fn previous(&self) -> Option<usize> {
+        match self.now > self.start() {
+            true => Some(self.entry(self.now - 1).code_ptr),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Execution tracing to JSON Lines, enabled with --trace <file>
+//!
+//! Each executed instruction produces one line:
+//! {"ptr":3,"instr":"Add(0, 1, 7)","fun":"add","depth":2,"reads":[{"reg":0,"value":"Int(5)"}],"writes":[...]}
+//!
+//! Writes contain registers written according to Instructions::reg_usage
+//! together with any other register whose value changed.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use runtime::runtime_types::{Context, Instructions, Types};
+
+use crate::{debugger, json::Json};
+
+/// selects traced instructions, empty filter list matches everything
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// instruction index ranges, end is exclusive
+    pub ranges: Vec<(usize, usize)>,
+    /// function names
+    pub funs: Vec<String>,
+    /// instruction kinds, same as variant names of Instructions (Add, Call, ...)
+    pub ops: Vec<String>,
+}
+
+impl Filter {
+    fn matches(&self, ctx: &Context, code_ptr: usize, kind: &str) -> bool {
+        (self.ranges.is_empty()
+            || self.ranges.iter().any(|(start, end)| (*start..*end).contains(&code_ptr)))
+            && (self.funs.is_empty()
+                || self.funs.iter().any(|fun| fun == debugger::fun_name(ctx, code_ptr)))
+            && (self.ops.is_empty() || self.ops.iter().any(|op| op == kind))
+    }
+}
+
+/// parses instruction range in format start..end
+pub fn parse_range(str: &str) -> Result<(usize, usize), String> {
+    let err = || format!("Invalid range '{str}', expected <start>..<end>");
+    let (start, end) = str.split_once("..").ok_or_else(err)?;
+    Ok((start.parse().map_err(|_| err())?, end.parse().map_err(|_| err())?))
+}
+
+/// runs program to the end, writing every instruction that matches filter to file
+pub fn run(ctx: &mut Context, path: &str, filter: &Filter) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    loop {
+        let code_ptr = ctx.code.ptr;
+        let instr = ctx.code.data[code_ptr];
+        let instr_str = format!("{instr:?}");
+        let kind = instr_str.split('(').next().unwrap_or_default();
+        if !filter.matches(ctx, code_ptr, kind) {
+            if !debugger::step(ctx) {
+                break;
+            }
+            continue;
+        }
+        let (mut reads, mut writes) = instr.reg_usage();
+        // arguments of calls are known only from fun_table
+        let callee = match instr {
+            Instructions::Call(fun_id) | Instructions::TailCall(fun_id) => Some(fun_id),
+            Instructions::CallDyn(reg) | Instructions::TailCallDyn(reg) => {
+                ctx.memory.registers[reg].as_fun().map(|(fun_id, _)| fun_id)
+            }
+            _ => None,
+        };
+        if let Some(fun) = callee.and_then(|fun_id| ctx.memory.fun_table.get(fun_id)) {
+            reads.extend(0..fun.params.len());
+        }
+        let fun = debugger::fun_name(ctx, code_ptr).to_string();
+        let depth = ctx.memory.stack.ptr;
+        let before = ctx.memory.registers;
+        let reads = regs_json(&before, &reads);
+        let running = debugger::step(ctx);
+        for (reg, (old, new)) in before.iter().zip(ctx.memory.registers.iter()).enumerate() {
+            if format!("{old:?}") != format!("{new:?}") && !writes.contains(&reg) {
+                writes.push(reg);
+            }
+        }
+        let line = Json::object(vec![
+            ("ptr", code_ptr.into()),
+            ("instr", instr_str.into()),
+            ("fun", fun.into()),
+            ("depth", depth.into()),
+            ("reads", reads),
+            ("writes", regs_json(&ctx.memory.registers, &writes)),
+        ]);
+        writeln!(out, "{line}")?;
+        if !running {
+            break;
+        }
+    }
+    out.flush()
+}
+
+fn regs_json(registers: &[Types], regs: &[usize]) -> Json {
+    regs.iter()
+        .map(|reg| {
+            Json::object(vec![
+                ("reg", (*reg).into()),
+                ("value", format!("{:?}", registers[*reg]).into()),
+            ])
+        })
+        .collect::<Vec<_>>()
+        .into()
+}